@@ -0,0 +1,97 @@
+; Flags test: shows VF after the arithmetic instructions as hex digits,
+; left to right. Expected with the default quirks:
+;
+;   0 1  1 0  0 1  1 1  1 1  7  0
+;   1
+
+        LD VA, 0x00             ; where the next digit is drawn
+        LD VB, 0x00
+
+        LD V1, 0x12             ; ADD without a carry: 0
+        LD V2, 0x23
+        ADD V1, V2
+        LD V0, VF
+        CALL show
+        LD V1, 0xFF             ; ADD with a carry: 1
+        LD V2, 0x01
+        ADD V1, V2
+        LD V0, VF
+        CALL show
+
+        LD V1, 0x05             ; SUB without a borrow: 1
+        LD V2, 0x03
+        SUB V1, V2
+        LD V0, VF
+        CALL show
+        LD V1, 0x03             ; SUB with a borrow: 0
+        LD V2, 0x05
+        SUB V1, V2
+        LD V0, VF
+        CALL show
+
+        LD V1, 0x05             ; SUBN with a borrow: 0
+        LD V2, 0x03
+        SUBN V1, V2
+        LD V0, VF
+        CALL show
+        LD V1, 0x03             ; SUBN without a borrow: 1
+        LD V2, 0x05
+        SUBN V1, V2
+        LD V0, VF
+        CALL show
+
+        LD V1, 0x01             ; SHR shifts out bit 0 of VX: 1
+        LD V2, 0x02
+        SHR V1, V2
+        LD V0, VF
+        CALL show
+        LD V1, 0x80             ; SHL shifts out bit 7 of VX: 1
+        LD V2, 0x40
+        SHL V1, V2
+        LD V0, VF
+        CALL show
+
+        LD VF, 0xFF             ; with VF as VX the flag wins: 1
+        LD V1, 0x01
+        ADD VF, V1
+        LD V0, VF
+        CALL show
+        LD VF, 0x05             ; 1
+        LD V1, 0x03
+        SUB VF, V1
+        LD V0, VF
+        CALL show
+
+        LD VF, 0x07             ; OR leaves VF alone: 7
+        LD V1, 0x01
+        LD V2, 0x02
+        OR V1, V2
+        LD V0, VF
+        CALL show
+
+        LD V0, 0x38             ; drawing a sprite sets VF to 0,
+        LD V1, 0x1A
+        LD V2, 0x00
+        LD F, V2
+        DRW V0, V1, 5
+        LD V0, VF
+        CALL show
+        LD V0, 0x38             ; and drawing it again to 1
+        LD V1, 0x1A
+        LD V2, 0x00
+        LD F, V2
+        DRW V0, V1, 5
+        LD V0, VF
+        CALL show
+
+end:    JP end
+
+; Draw the digit in V0 at (VA, VB) and move on, 12 digits to a line.
+show:   LD F, V0
+        DRW VA, VB, 5
+        ADD VA, 0x05
+        SE VA, 0x3C
+        RET
+        LD VA, 0x00
+        ADD VB, 0x06
+        RET
@@ -0,0 +1,41 @@
+; Keypad test: shows the keys it sees as hex digits. Driven by the script in
+; the keypad conformance case:
+;
+;   3 and A                 pressed and released during two FX0A waits
+;   5                       held until EX9E sees it, then let go for EXA1,
+;                           which shows E
+;   7                       pressed before the last FX0A starts, so it only
+;                           counts once released
+
+        LD VA, 0x00             ; where the next digit is drawn
+        LD VB, 0x00
+
+        LD V0, K
+        CALL show
+        LD V0, K
+        CALL show
+
+        LD V1, 0x05
+down:   SKP V1                  ; wait for 5 to be held
+        JP down
+        LD V0, V1
+        CALL show
+up:     SKNP V1                 ; and for it to be let go
+        JP up
+        LD V0, 0x0E
+        CALL show
+
+        LD V0, K
+        CALL show
+
+end:    JP end
+
+; Draw the digit in V0 at (VA, VB) and move on, 12 digits to a line.
+show:   LD F, V0
+        DRW VA, VB, 5
+        ADD VA, 0x05
+        SE VA, 0x3C
+        RET
+        LD VA, 0x00
+        ADD VB, 0x06
+        RET
@@ -0,0 +1,65 @@
+; Quirks test: shows the results of the instructions that differ between
+; interpreters as hex digits, then draws an 8 across the right edge.
+;
+;   shift                   4, or 1 with the shift quirk
+;   FX55/FX65 and I         1 leaving I unchanged, 3 with memoryIncrementByX
+;                           and 9 incrementing I by X + 1
+;   BNNN                    1, or 2 with the jump quirk
+;   8XY1 and VF             5, or 0 with the logic quirk
+;   sprite at x = 62        wraps to the left edge, or is clipped with the
+;                           wrap quirk off
+
+        LD VA, 0x00             ; where the next digit is drawn
+        LD VB, 0x00
+
+        LD V1, 0x03             ; V1 = VY >> 1 = 4, or VX >> 1 = 1
+        LD V2, 0x08
+        SHR V1, V2
+        LD V0, V1
+        CALL show
+
+        LD I, buf               ; store 1 2 3 over buf, then read the byte
+        LD V0, 0x01             ; I points at afterwards
+        LD V1, 0x02
+        LD V2, 0x03
+        LD [I], V2
+        LD V0, [I]
+        CALL show
+
+        LD V0, 0x00             ; BNNN adds V0 = 0, BXNN adds V2 = 2
+        LD V2, 0x02
+        JP V0, jumps
+jumps:  JP jump1
+        JP jump2
+jump1:  LD V5, 0x01
+        JP jumped
+jump2:  LD V5, 0x02
+jumped: LD V0, V5
+        CALL show
+
+        LD VF, 0x05             ; OR leaves VF at 5 or resets it
+        LD V1, 0x01
+        OR V1, V1
+        LD V0, VF
+        CALL show
+
+        LD V0, 0x3E             ; an 8 at (62, 20)
+        LD V1, 0x14
+        LD V2, 0x08
+        LD F, V2
+        DRW V0, V1, 5
+
+end:    JP end
+
+; Draw the digit in V0 at (VA, VB) and move on, 12 digits to a line.
+show:   LD F, V0
+        DRW VA, VB, 5
+        ADD VA, 0x05
+        SE VA, 0x3C
+        RET
+        LD VA, 0x00
+        ADD VB, 0x06
+        RET
+
+; I ends up at buf, buf + 2 or buf + 3
+buf:    DB 0x00, 0x00, 0x00, 0x09
@@ -0,0 +1,196 @@
+//! An assembler for the mnemonics printed by `disasm`, used to build the test
+//! ROMs in `rom/src` so their sources and binaries can be checked against
+//! each other.
+//!
+//! A line holds an optional `label:` and an instruction, and `;` starts a
+//! comment. Addresses may be given as labels, and `DB` emits bytes.
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Arg {
+    V(u16),
+    Num(u16),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+}
+
+/// Assemble `source` for loading at `origin`.
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let mut lines = Vec::new();
+    let mut labels = HashMap::new();
+    let mut addr = origin;
+    for (n, line) in source.lines().enumerate() {
+        let mut line = line.split(';').next().unwrap().trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            if labels.insert(label.trim(), addr).is_some() {
+                return Err(format!("line {}: {} defined twice", n + 1, label));
+            }
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let (mnemonic, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args: Vec<&str> = args
+            .split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .collect();
+        addr += if mnemonic == "DB" {
+            args.len() as u16
+        } else {
+            2
+        };
+        lines.push((n + 1, mnemonic, args));
+    }
+
+    let mut out = Vec::new();
+    for (n, mnemonic, args) in lines {
+        let args = args
+            .iter()
+            .map(|a| arg(a, &labels))
+            .collect::<Result<Vec<Arg>, String>>()
+            .map_err(|e| format!("line {}: {}", n, e))?;
+        if mnemonic == "DB" {
+            for a in args {
+                match a {
+                    Arg::Num(b) if b <= 0xff => out.push(b as u8),
+                    _ => return Err(format!("line {}: DB takes bytes", n)),
+                }
+            }
+            continue;
+        }
+        let word = encode(mnemonic, &args)
+            .ok_or_else(|| format!("line {}: can't assemble {} {:?}", n, mnemonic, args))?;
+        out.extend(word.to_be_bytes());
+    }
+    Ok(out)
+}
+
+fn arg(s: &str, labels: &HashMap<&str, u16>) -> Result<Arg, String> {
+    let a = match s {
+        "I" => Arg::I,
+        "[I]" => Arg::IndirectI,
+        "DT" => Arg::Dt,
+        "ST" => Arg::St,
+        "K" => Arg::K,
+        "F" => Arg::F,
+        "B" => Arg::B,
+        _ if s.len() == 2 && s.starts_with('V') => {
+            Arg::V(u16::from_str_radix(&s[1..], 16).map_err(|_| format!("bad register {}", s))?)
+        }
+        _ => {
+            let num = match s.strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16).ok(),
+                None => s.parse().ok(),
+            };
+            match num.or_else(|| labels.get(s).copied()) {
+                Some(num) => Arg::Num(num),
+                None => return Err(format!("unknown label {}", s)),
+            }
+        }
+    };
+    Ok(a)
+}
+
+/// Encode an instruction, if its operands fit.
+fn encode(mnemonic: &str, args: &[Arg]) -> Option<u16> {
+    use Arg::*;
+    let nnn = |n: u16| (n <= 0xfff).then_some(n);
+    let xnn = |x: u16, n: u16| (n <= 0xff).then_some(x << 8 | n);
+    let xy = |x: u16, y: u16, n: u16| x << 8 | y << 4 | n;
+    let word = match (mnemonic, args) {
+        ("CLS", []) => 0x00e0,
+        ("RET", []) => 0x00ee,
+        ("SYS", [Num(n)]) => nnn(*n)?,
+        ("JP", [Num(n)]) => 0x1000 | nnn(*n)?,
+        ("JP", [V(0), Num(n)]) => 0xb000 | nnn(*n)?,
+        ("CALL", [Num(n)]) => 0x2000 | nnn(*n)?,
+        ("SE", [V(x), Num(n)]) => 0x3000 | xnn(*x, *n)?,
+        ("SNE", [V(x), Num(n)]) => 0x4000 | xnn(*x, *n)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | xy(*x, *y, 0),
+        ("SNE", [V(x), V(y)]) => 0x9000 | xy(*x, *y, 0),
+        ("LD", [V(x), Num(n)]) => 0x6000 | xnn(*x, *n)?,
+        ("ADD", [V(x), Num(n)]) => 0x7000 | xnn(*x, *n)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0x0),
+        ("OR", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0x1),
+        ("AND", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0x2),
+        ("XOR", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0x3),
+        ("ADD", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0x4),
+        ("SUB", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0x5),
+        ("SHR", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0x6),
+        ("SUBN", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0x7),
+        ("SHL", [V(x), V(y)]) => 0x8000 | xy(*x, *y, 0xe),
+        ("LD", [I, Num(n)]) => 0xa000 | nnn(*n)?,
+        ("RND", [V(x), Num(n)]) => 0xc000 | xnn(*x, *n)?,
+        ("DRW", [V(x), V(y), Num(n)]) if *n <= 0xf => 0xd000 | xy(*x, *y, *n),
+        ("SKP", [V(x)]) => 0xe09e | x << 8,
+        ("SKNP", [V(x)]) => 0xe0a1 | x << 8,
+        ("AUDIO", []) => 0xf002,
+        ("LD", [V(x), Dt]) => 0xf007 | x << 8,
+        ("LD", [V(x), K]) => 0xf00a | x << 8,
+        ("LD", [Dt, V(x)]) => 0xf015 | x << 8,
+        ("LD", [St, V(x)]) => 0xf018 | x << 8,
+        ("ADD", [I, V(x)]) => 0xf01e | x << 8,
+        ("LD", [F, V(x)]) => 0xf029 | x << 8,
+        ("LD", [B, V(x)]) => 0xf033 | x << 8,
+        ("PITCH", [V(x)]) => 0xf03a | x << 8,
+        ("LD", [IndirectI, V(x)]) => 0xf055 | x << 8,
+        ("LD", [V(x), IndirectI]) => 0xf065 | x << 8,
+        ("DW", [Num(n)]) => *n,
+        _ => return None,
+    };
+    Some(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode;
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn assemble_resolves_labels() {
+        let source = "
+            start:  LD V0, 0x05   ; a comment
+                    CALL sub
+            halt:   JP halt
+            sub:    DRW V0, V1, 5
+                    RET
+            data:   DB 0xF0, 144
+        ";
+        assert_eq!(
+            assemble(source, 0x200).unwrap(),
+            [0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0xd0, 0x15, 0x00, 0xee, 0xf0, 0x90]
+        );
+    }
+
+    #[test]
+    fn assemble_reports_errors_with_the_line() {
+        assert_eq!(
+            assemble("JP nowhere", 0x200).unwrap_err(),
+            "line 1: unknown label nowhere"
+        );
+        assert!(assemble("\nLD V0, 0x100", 0x200)
+            .unwrap_err()
+            .starts_with("line 2:"));
+        assert!(assemble("a:\na: CLS", 0x200).is_err());
+    }
+
+    #[test]
+    fn assemble_reads_back_every_disassembled_instruction() {
+        for instr in 0..=u16::MAX {
+            let text = disassemble(instr);
+            let bytes = assemble(&text, 0x200).unwrap();
+            // unused bits such as the N of 5XYN read back as 0
+            let word = u16::from_be_bytes([bytes[0], bytes[1]]);
+            assert_eq!(decode(word), decode(instr), "{}", text);
+        }
+    }
+}
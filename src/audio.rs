@@ -0,0 +1,170 @@
+use std::io;
+use std::path::Path;
+
+pub const SAMPLE_RATE: u32 = 44100;
+/// Number of samples generated for every emulated 60 Hz frame.
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// Frequency of the plain buzzer used until a ROM loads an XO-CHIP pattern.
+const BUZZER_HZ: f64 = 440.0;
+const AMPLITUDE: i16 = 8000;
+
+/// The sound state of the emulator during one frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sound {
+    pub active: bool,
+    /// XO-CHIP 1-bit audio pattern, played back most significant bit first.
+    pub pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+}
+
+impl Sound {
+    /// Playback rate of the pattern in bits per second.
+    fn pattern_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+}
+
+/// Generates the beeper waveform one frame at a time.
+///
+/// Only emulated state is used, so the output is identical no matter how fast
+/// the host runs the emulator.
+#[derive(Default)]
+pub struct Synth {
+    phase: f64,
+}
+
+impl Synth {
+    pub fn render_frame(&mut self, sound: &Sound, out: &mut [i16; SAMPLES_PER_FRAME]) {
+        if !sound.active {
+            self.phase = 0.0;
+            out.fill(0);
+            return;
+        }
+        for sample in out.iter_mut() {
+            let high = match sound.pattern {
+                Some(pattern) => {
+                    let bit = self.phase as usize % 128;
+                    self.phase = (self.phase + sound.pattern_rate() / SAMPLE_RATE as f64) % 128.0;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => {
+                    let high = self.phase < 0.5;
+                    self.phase = (self.phase + BUZZER_HZ / SAMPLE_RATE as f64) % 1.0;
+                    high
+                }
+            };
+            *sample = if high { AMPLITUDE } else { -AMPLITUDE };
+        }
+    }
+}
+
+/// Records the generated audio to a 16-bit mono WAV file.
+pub struct WavRecorder {
+    writer: hound::WavWriter<io::BufWriter<std::fs::File>>,
+    synth: Synth,
+}
+
+impl WavRecorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::create(path, spec).map_err(io::Error::other)?;
+        Ok(WavRecorder {
+            writer,
+            synth: Synth::default(),
+        })
+    }
+
+    /// Append the audio of one emulated frame.
+    pub fn push_frame(&mut self, sound: &Sound) -> io::Result<()> {
+        let mut samples = [0; SAMPLES_PER_FRAME];
+        self.synth.render_frame(sound, &mut samples);
+        for s in samples {
+            self.writer.write_sample(s).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finalize().map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(synth: &mut Synth, sound: &Sound) -> [i16; SAMPLES_PER_FRAME] {
+        let mut out = [0; SAMPLES_PER_FRAME];
+        synth.render_frame(sound, &mut out);
+        out
+    }
+
+    #[test]
+    fn synth_is_silent_when_inactive() {
+        let sound = Sound {
+            active: false,
+            pattern: None,
+            pitch: 64,
+        };
+        assert!(render(&mut Synth::default(), &sound)
+            .iter()
+            .all(|s| *s == 0));
+    }
+
+    #[test]
+    fn synth_plays_a_square_wave_buzzer() {
+        let sound = Sound {
+            active: true,
+            pattern: None,
+            pitch: 64,
+        };
+        // A 440 Hz square wave is high for the first 50 samples of every period
+        let out = render(&mut Synth::default(), &sound);
+        assert!(out[..=50].iter().all(|s| *s == AMPLITUDE));
+        assert!(out[51..=100].iter().all(|s| *s == -AMPLITUDE));
+        assert_eq!(out[101], AMPLITUDE);
+    }
+
+    #[test]
+    fn synth_plays_xo_chip_patterns() {
+        // At the default pitch the pattern plays at 4000 bits per second, so
+        // every bit lasts a little over 11 samples.
+        let mut pattern = [0x00; 16];
+        pattern[0] = 0x80;
+        let sound = Sound {
+            active: true,
+            pattern: Some(pattern),
+            pitch: 64,
+        };
+        let out = render(&mut Synth::default(), &sound);
+        assert!(out[..12].iter().all(|s| *s == AMPLITUDE));
+        assert!(out[12..].iter().all(|s| *s == -AMPLITUDE));
+    }
+
+    #[test]
+    fn wav_recorder_writes_a_frame_of_samples_per_frame() {
+        let path = std::env::temp_dir().join("chip8-rust-wav-recorder-test.wav");
+        let mut r = WavRecorder::create(&path).unwrap();
+        let sound = Sound {
+            active: true,
+            pattern: None,
+            pitch: 64,
+        };
+        r.push_frame(&sound).unwrap();
+        r.push_frame(&Sound {
+            active: false,
+            ..sound
+        })
+        .unwrap();
+        r.finish().unwrap();
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.duration() as usize, SAMPLES_PER_FRAME * 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
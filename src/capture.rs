@@ -0,0 +1,236 @@
+use crate::palette::Palette;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of emulated frames per second the recorder is fed with.
+const FPS: u32 = 60;
+
+/// Scale an image of one byte per pixel, such as the display, up by an integer factor.
+pub fn scale_pixels(pixels: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    let height = pixels.len() / width;
+    let mut out = Vec::with_capacity(pixels.len() * scale * scale);
+    for y in 0..height * scale {
+        let row = &pixels[(y / scale) * width..][..width];
+        for x in 0..width * scale {
+            out.push(row[x / scale]);
+        }
+    }
+    out
+}
+
+/// Write the display as a PNG image.
+pub fn save_png(
+    path: &Path,
+    pixels: &[u8],
+    width: usize,
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    let scaled = scale_pixels(pixels, width, scale);
+    let rgba: Vec<u8> = scaled
+        .iter()
+        .flat_map(|p| palette.colour(*p != 0))
+        .collect();
+
+    let height = pixels.len() / width;
+    write_png(path, &rgba, width * scale, height * scale)
+}
+
+/// Write RGBA pixels as a PNG image.
+pub fn write_png(path: &Path, rgba: &[u8], width: usize, height: usize) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(rgba).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// Records emulated frames into an animated GIF.
+///
+/// Consecutive identical frames are merged into a single GIF frame with a longer
+/// delay, and delays are distributed so the animation keeps 60 fps on average
+/// despite GIF only supporting hundredths of a second.
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: usize,
+    scale: usize,
+    pending: Option<Vec<u8>>,
+    pending_frames: u32,
+    frames_written: u64,
+    centis_written: u64,
+}
+
+impl GifRecorder {
+    pub fn create(
+        path: &Path,
+        width: usize,
+        height: usize,
+        scale: usize,
+        palette: &Palette,
+    ) -> io::Result<Self> {
+        let global_palette = [
+            palette.off[0],
+            palette.off[1],
+            palette.off[2],
+            palette.on[0],
+            palette.on[1],
+            palette.on[2],
+        ];
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = gif::Encoder::new(
+            file,
+            (width * scale) as u16,
+            (height * scale) as u16,
+            &global_palette,
+        )
+        .map_err(io::Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+        Ok(GifRecorder {
+            encoder,
+            width,
+            scale,
+            pending: None,
+            pending_frames: 0,
+            frames_written: 0,
+            centis_written: 0,
+        })
+    }
+
+    /// Add one emulated frame to the recording.
+    pub fn push_frame(&mut self, pixels: &[u8]) -> io::Result<()> {
+        if self.pending.as_deref() == Some(pixels) {
+            self.pending_frames += 1;
+            return Ok(());
+        }
+        self.flush()?;
+        self.pending = Some(pixels.to_vec());
+        self.pending_frames = 1;
+        Ok(())
+    }
+
+    /// Write the last frame and close the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let pixels = match self.pending.take() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        self.frames_written += self.pending_frames as u64;
+        let centis = (self.frames_written * 100 + FPS as u64 / 2) / FPS as u64;
+        let delay = (centis - self.centis_written).min(u16::MAX as u64);
+        self.centis_written = centis;
+
+        let scaled = scale_pixels(&pixels, self.width, self.scale);
+        let frame = gif::Frame {
+            width: (self.width * self.scale) as u16,
+            height: (scaled.len() / (self.width * self.scale)) as u16,
+            delay: delay as u16,
+            buffer: scaled.into(),
+            ..gif::Frame::default()
+        };
+        self.encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+}
+
+/// Build a path like `dir/<rom name>-20221104-201502.png` that does not exist yet.
+pub fn capture_path(dir: &Path, rom: &Path, ext: &str) -> PathBuf {
+    let stem = rom
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "chip8".to_string());
+    let base = format!("{}-{}", stem, timestamp(SystemTime::now()));
+    let mut path = dir.join(format!("{}.{}", base, ext));
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", base, n, ext));
+        n += 1;
+    }
+    path
+}
+
+/// Format a time as `YYYYMMDD-HHMMSS` in UTC.
+pub fn timestamp(t: SystemTime) -> String {
+    let secs = t
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Convert days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn scale_pixels_repeats_rows_and_columns() {
+        let scaled = scale_pixels(&[1, 0, 0, 1], 2, 2);
+        assert_eq!(scaled, [1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn timestamp_formats_utc_dates() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+        let t = UNIX_EPOCH + Duration::from_secs(1_667_592_902);
+        assert_eq!(timestamp(t), "20221104-201502");
+        let leap = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(timestamp(leap), "20000229-000000");
+    }
+
+    #[test]
+    fn capture_path_uses_rom_name() {
+        let p = capture_path(Path::new("shots"), Path::new("rom/ibm_logo.ch8"), "png");
+        let name = p.file_name().unwrap().to_string_lossy();
+        assert!(p.starts_with("shots"));
+        assert!(name.starts_with("ibm_logo-"));
+        assert!(name.ends_with(".png"));
+    }
+
+    #[test]
+    fn gif_recorder_merges_identical_frames() {
+        let path = std::env::temp_dir().join("chip8-rust-gif-recorder-test.gif");
+        let mut r = GifRecorder::create(&path, 2, 1, 1, &Palette::default()).unwrap();
+        for _ in 0..3 {
+            r.push_frame(&[1, 0]).unwrap();
+        }
+        r.push_frame(&[0, 1]).unwrap();
+        assert_eq!(r.frames_written, 3);
+        assert_eq!(r.centis_written, 5);
+        r.flush().unwrap();
+        assert_eq!(r.frames_written, 4);
+        assert_eq!(r.centis_written, 7);
+        r.finish().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+//! The RCA CDP1802 CPU of the COSMAC VIP, for machine code called by `0NNN`
+//! and for running the original CHIP-8 interpreter.
+
+/// Memory and I/O seen by the CPU.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    /// Read a byte of code through the program counter: an opcode, an
+    /// immediate operand or a branch target.
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+    fn write(&mut self, addr: u16, value: u8);
+    /// `OUT 1` to `OUT 7`.
+    fn output(&mut self, port: u8, value: u8);
+    /// `INP 1` to `INP 7`.
+    fn input(&mut self, port: u8) -> u8;
+    /// External flags `EF1` to `EF4`.
+    fn flag(&mut self, n: u8) -> bool;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cdp1802 {
+    /// Scratchpad registers R0 to RF.
+    pub r: [u16; 16],
+    pub d: u8,
+    pub df: bool,
+    /// Number of the register used as program counter.
+    pub p: u8,
+    /// Number of the register used as data pointer.
+    pub x: u8,
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+    /// Stopped by `IDL` until an interrupt.
+    pub idle: bool,
+}
+
+impl Cdp1802 {
+    /// State after a reset: R0 is the program counter, starting at 0.
+    pub fn reset() -> Self {
+        Cdp1802 {
+            ie: true,
+            ..Cdp1802::default()
+        }
+    }
+
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        let p = self.p as usize;
+        let value = bus.fetch(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        value
+    }
+
+    /// Execute one instruction, returning the machine cycles it took.
+    pub fn step(&mut self, bus: &mut impl Bus) -> u32 {
+        if self.idle {
+            return 1;
+        }
+        let opcode = self.fetch(bus);
+        let (i, n) = (opcode >> 4, (opcode & 0xf) as usize);
+        let x = self.x as usize;
+        match i {
+            0x0 if n == 0 => self.idle = true,
+            // LDN
+            0x0 => self.d = bus.read(self.r[n]),
+            // INC, DEC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            // short branches
+            0x3 => {
+                let taken = self.condition(bus, n & 7) != (n & 8 != 0);
+                let p = self.p as usize;
+                if taken {
+                    let target = bus.fetch(self.r[p]);
+                    self.r[p] = self.r[p] & 0xff00 | target as u16;
+                } else {
+                    self.r[p] = self.r[p].wrapping_add(1);
+                }
+            }
+            // LDA, STR
+            0x4 => {
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            0x5 => bus.write(self.r[n], self.d),
+            // IRX
+            0x6 if n == 0 => self.r[x] = self.r[x].wrapping_add(1),
+            // OUT
+            0x6 if n < 8 => {
+                let value = bus.read(self.r[x]);
+                bus.output(n as u8, value);
+                self.r[x] = self.r[x].wrapping_add(1);
+            }
+            // 68 is an extended opcode on the CDP1804 only
+            0x6 if n == 8 => {}
+            // INP
+            0x6 => {
+                self.d = bus.input(n as u8 - 8);
+                bus.write(self.r[x], self.d);
+            }
+            0x7 => self.execute_7(bus, n),
+            // GLO, GHI, PLO, PHI
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xa => self.r[n] = self.r[n] & 0xff00 | self.d as u16,
+            0xb => self.r[n] = self.r[n] & 0x00ff | (self.d as u16) << 8,
+            0xc => {
+                self.long_branch(bus, n);
+                return 3;
+            }
+            // SEP, SEX
+            0xd => self.p = n as u8,
+            0xe => self.x = n as u8,
+            _ => self.execute_f(bus, n),
+        }
+        2
+    }
+
+    /// Condition tested by branches 0 to 7: always, Q, D == 0, DF and EF1-4.
+    fn condition(&mut self, bus: &mut impl Bus, n: usize) -> bool {
+        match n {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            _ => bus.flag(n as u8 - 3),
+        }
+    }
+
+    fn long_branch(&mut self, bus: &mut impl Bus, n: usize) {
+        let p = self.p as usize;
+        // C4 is NOP, C5 to CF except C8 skip when the condition of the
+        // matching branch is true
+        let (skip, taken) = match n {
+            0x4 => (true, false),
+            0x5 => (true, !self.q),
+            0x6 => (true, self.d != 0),
+            0x7 => (true, !self.df),
+            0x8 => (true, true),
+            0xc => (true, self.ie),
+            0xd => (true, self.q),
+            0xe => (true, self.d == 0),
+            0xf => (true, self.df),
+            _ => (false, self.condition(bus, n & 3) != (n & 8 != 0)),
+        };
+        if skip {
+            if taken {
+                self.r[p] = self.r[p].wrapping_add(2);
+            }
+        } else if taken {
+            let hi = bus.fetch(self.r[p]);
+            let lo = bus.fetch(self.r[p].wrapping_add(1));
+            self.r[p] = (hi as u16) << 8 | lo as u16;
+        } else {
+            self.r[p] = self.r[p].wrapping_add(2);
+        }
+    }
+
+    fn execute_7(&mut self, bus: &mut impl Bus, n: usize) {
+        let x = self.x as usize;
+        match n {
+            // RET, DIS
+            0x0 | 0x1 => {
+                let value = bus.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+                self.x = value >> 4;
+                self.p = value & 0xf;
+                self.ie = n == 0;
+            }
+            // LDXA, STXD
+            0x2 => {
+                self.d = bus.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+            }
+            0x3 => {
+                bus.write(self.r[x], self.d);
+                self.r[x] = self.r[x].wrapping_sub(1);
+            }
+            // ADC, SDB, SMB and their immediate forms
+            0x4 | 0x5 | 0x7 | 0xc | 0xd | 0xf => {
+                let operand = if n < 8 {
+                    bus.read(self.r[x])
+                } else {
+                    self.fetch(bus)
+                };
+                self.arithmetic(n & 7, operand, self.df);
+            }
+            // SHRC, SHLC
+            0x6 => {
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            0xe => {
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+            // SAV
+            0x8 => bus.write(self.r[x], self.t),
+            // MARK
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ, SEQ
+            0xa => self.q = false,
+            _ => self.q = true,
+        }
+    }
+
+    fn execute_f(&mut self, bus: &mut impl Bus, n: usize) {
+        let operand = match n {
+            // SHR, SHL
+            0x6 | 0xe => 0,
+            0x0..=0x7 => bus.read(self.r[self.x as usize]),
+            _ => self.fetch(bus),
+        };
+        match n & 7 {
+            // LDX, LDI
+            0x0 => self.d = operand,
+            0x1 => self.d |= operand,
+            0x2 => self.d &= operand,
+            0x3 => self.d ^= operand,
+            0x6 if n == 6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            0x6 => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            // ADD, SD and SM without carry in
+            op => self.arithmetic(op, operand, op != 4),
+        }
+    }
+
+    /// Add (4), subtract D from the operand (5) or the operand from D (7).
+    /// For subtraction DF is set when there is no borrow, and a borrow in is
+    /// a clear `carry`.
+    fn arithmetic(&mut self, op: usize, operand: u8, carry: bool) {
+        let (a, b) = match op {
+            4 => (self.d as u16, operand as u16),
+            5 => (operand as u16, !self.d as u16),
+            _ => (self.d as u16, !operand as u16),
+        };
+        let result = a + b + carry as u16;
+        self.d = result as u8;
+        self.df = result > 0xff;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ram {
+        mem: Vec<u8>,
+        out: Vec<(u8, u8)>,
+    }
+
+    impl Bus for Ram {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.mem[addr as usize]
+        }
+        fn write(&mut self, addr: u16, value: u8) {
+            self.mem[addr as usize] = value;
+        }
+        fn output(&mut self, port: u8, value: u8) {
+            self.out.push((port, value));
+        }
+        fn input(&mut self, port: u8) -> u8 {
+            port * 0x10
+        }
+        fn flag(&mut self, n: u8) -> bool {
+            n == 3
+        }
+    }
+
+    fn run(code: &[u8], steps: usize) -> (Cdp1802, Ram) {
+        let mut ram = Ram {
+            mem: vec![0; 0x100],
+            out: Vec::new(),
+        };
+        ram.mem[..code.len()].copy_from_slice(code);
+        let mut cpu = Cdp1802::reset();
+        for _ in 0..steps {
+            cpu.step(&mut ram);
+        }
+        (cpu, ram)
+    }
+
+    #[test]
+    fn cpu_loads_stores_and_moves_registers() {
+        // LDI 80, PHI R3, LDI 42, PLO R3, GHI R3, LDI 90, PLO R4, GLO R3, STR R4
+        let (cpu, ram) = run(
+            &[
+                0xf8, 0x80, 0xb3, 0xf8, 0x42, 0xa3, 0x93, 0xf8, 0x90, 0xa4, 0x83, 0x54,
+            ],
+            9,
+        );
+        assert_eq!(cpu.r[3], 0x8042);
+        assert_eq!(ram.mem[0x90], 0x42);
+        assert_eq!(cpu.r[0], 12);
+    }
+
+    #[test]
+    fn cpu_does_arithmetic_with_carry() {
+        // LDI F0, ADI 20
+        let (cpu, _) = run(&[0xf8, 0xf0, 0xfc, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+        // LDI 10, SMI 20: borrow clears DF
+        let (cpu, _) = run(&[0xf8, 0x10, 0xff, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0xf0, false));
+        // LDI 10, SDI 20
+        let (cpu, _) = run(&[0xf8, 0x10, 0xfd, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+        // LDI 81, SHL, SHRC
+        let (cpu, _) = run(&[0xf8, 0x81, 0xfe, 0x76], 3);
+        assert_eq!((cpu.d, cpu.df), (0x81, false));
+    }
+
+    #[test]
+    fn cpu_branches_and_switches_program_counter() {
+        // LDI 00, BZ 06, SEQ, ..., 06: BN3 00, LBR 0020
+        let (cpu, _) = run(
+            &[
+                0xf8, 0x00, 0x32, 0x06, 0x7b, 0x00, 0x3e, 0x00, 0xc0, 0x00, 0x20,
+            ],
+            4,
+        );
+        assert_eq!(cpu.r[0], 0x20);
+        assert!(!cpu.q);
+        // LDI 30, PLO R5, SEP R5
+        let (cpu, _) = run(&[0xf8, 0x30, 0xa5, 0xd5], 4);
+        assert_eq!((cpu.p, cpu.r[5]), (5, 0x31));
+    }
+
+    #[test]
+    fn cpu_does_io_and_returns() {
+        // LDI 10, PLO R2, SEX 2, OUT 2, INP 3, LDI 23, STR R2, RET
+        let code = [0xf8, 0x10, 0xa2, 0xe2, 0x62, 0x6b, 0xf8, 0x23, 0x52, 0x70];
+        let (cpu, ram) = run(&code, 5);
+        assert_eq!(ram.out, [(2, 0x00)]);
+        assert_eq!((cpu.d, ram.mem[0x11]), (0x30, 0x30));
+        let (cpu, _) = run(&code, 8);
+        assert_eq!((cpu.x, cpu.p, cpu.ie), (2, 3, true));
+        // IDL
+        let (cpu, _) = run(&[0x00, 0x7b], 2);
+        assert!(cpu.idle && !cpu.q);
+    }
+}
@@ -0,0 +1,402 @@
+//! Cheats: searching memory for values such as the number of lives, and
+//! freezing them.
+//!
+//! A search starts from a snapshot of memory with every address as a candidate.
+//! Each filter, e.g. "equal to 3" or "decreased", keeps the candidates matching
+//! it compared to the previous snapshot and takes a new one. Frozen values are
+//! written back to memory at the end of every frame and saved to a file per
+//! ROM, which is loaded again on startup.
+
+use crate::options::parse_address;
+use crate::Emulator;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const HELP: &str = "\
+  search [reset]            start a memory search from a snapshot of memory
+  search <eq N|changed|unchanged|increased|decreased>
+                            keep the candidates matching since the last search
+  candidates                list the candidate addresses
+  freeze <ADDR> [VALUE] [NAME]
+                            keep ADDR at VALUE [default: its current value]
+  unfreeze <ADDR>           stop freezing ADDR
+  cheats                    list the frozen addresses
+  poke <ADDR> <VALUE>       write a value to memory once";
+
+/// Candidates listed at most, the rest are counted.
+const MAX_LISTED: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Filter {
+    pub fn parse(args: &[&str]) -> Result<Filter, String> {
+        Ok(match args {
+            ["eq", value] => Filter::Equal(parse_value(value)?),
+            ["changed"] => Filter::Changed,
+            ["unchanged"] => Filter::Unchanged,
+            ["increased"] => Filter::Increased,
+            ["decreased"] => Filter::Decreased,
+            _ => return Err(format!("invalid search '{}'", args.join(" "))),
+        })
+    }
+
+    fn matches(self, old: u8, new: u8) -> bool {
+        match self {
+            Filter::Equal(value) => new == value,
+            Filter::Changed => new != old,
+            Filter::Unchanged => new == old,
+            Filter::Increased => new > old,
+            Filter::Decreased => new < old,
+        }
+    }
+}
+
+pub struct Search {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl Search {
+    /// Start a search with every address as a candidate.
+    pub fn new(mem: &[u8]) -> Search {
+        Search {
+            snapshot: mem.to_vec(),
+            candidates: (0..mem.len()).map(|a| a as u16).collect(),
+        }
+    }
+
+    /// Keep the candidates matching the filter and take a new snapshot.
+    pub fn filter(&mut self, mem: &[u8], filter: Filter) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|a| {
+            let a = *a as usize;
+            filter.matches(snapshot[a], mem[a])
+        });
+        self.snapshot = mem.to_vec();
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Freeze {
+    pub addr: u16,
+    pub value: u8,
+    pub name: String,
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    pub freezes: Vec<Freeze>,
+    pub search: Option<Search>,
+    /// Where the freezes are saved when they change.
+    pub path: Option<PathBuf>,
+}
+
+impl Cheats {
+    /// Load the cheats saved in a file, no cheats when it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Cheats, String> {
+        let freezes = match fs::read_to_string(path) {
+            Ok(text) => parse(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.to_string()),
+        };
+        Ok(Cheats {
+            freezes,
+            search: None,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = "# chip8-rust cheats, one per line: ADDR VALUE [NAME]\n".to_string();
+        for f in &self.freezes {
+            let _ = writeln!(text, "0x{:03X} 0x{:02X} {}", f.addr, f.value, f.name);
+        }
+        fs::write(path, text)
+    }
+
+    /// Freeze an address, replacing an earlier freeze of it.
+    pub fn freeze(&mut self, freeze: Freeze) {
+        self.unfreeze(freeze.addr);
+        self.freezes.push(freeze);
+        self.freezes.sort_by_key(|f| f.addr);
+    }
+
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        let len = self.freezes.len();
+        self.freezes.retain(|f| f.addr != addr);
+        self.freezes.len() != len
+    }
+}
+
+/// Write the frozen values to memory, the way programs see it.
+pub fn apply(e: &mut Emulator) {
+    for n in 0..e.cheats.freezes.len() {
+        let Freeze { addr, value, .. } = e.cheats.freezes[n];
+        e.poke(addr as usize, value);
+    }
+}
+
+/// Parse a cheat file, ignoring empty lines and `#` comments.
+pub fn parse(text: &str) -> Result<Vec<Freeze>, String> {
+    let mut freezes = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.splitn(3, char::is_whitespace);
+        let parsed = match (words.next(), words.next()) {
+            (Some(addr), Some(value)) => parse_address(addr).and_then(|addr| {
+                Ok(Freeze {
+                    addr,
+                    value: parse_value(value)?,
+                    name: words.next().unwrap_or("").trim().to_string(),
+                })
+            }),
+            _ => Err("expected ADDR VALUE [NAME]".to_string()),
+        };
+        freezes.push(parsed.map_err(|e| format!("line {}: {}", n + 1, e))?);
+    }
+    Ok(freezes)
+}
+
+fn parse_value(s: &str) -> Result<u8, String> {
+    match parse_address(s) {
+        Ok(v) if v <= 0xff => Ok(v as u8),
+        _ => Err(format!("invalid value '{}'", s)),
+    }
+}
+
+/// Memory as the program sees it, including a display mapped into it.
+fn memory(e: &Emulator) -> Vec<u8> {
+    (0..e.mem.len()).map(|a| e.peek(a)).collect()
+}
+
+fn list_candidates(e: &Emulator, search: &Search) -> String {
+    let mut out = format!("{} candidates", search.candidates.len());
+    for a in search.candidates.iter().take(MAX_LISTED) {
+        let _ = write!(out, "\n0x{:03X} = 0x{:02X}", a, e.peek(*a as usize));
+    }
+    if search.candidates.len() > MAX_LISTED {
+        let _ = write!(
+            out,
+            "\n... and {} more",
+            search.candidates.len() - MAX_LISTED
+        );
+    }
+    out
+}
+
+fn save(e: &Emulator, message: String) -> String {
+    match e.cheats.save() {
+        Ok(()) => message,
+        Err(err) => format!("{}, saving cheats failed: {}", message, err),
+    }
+}
+
+/// Run a cheat command, `None` when the command isn't one.
+pub fn execute(e: &mut Emulator, cmd: &str, args: &[&str]) -> Option<Result<String, String>> {
+    let result = match (cmd, args) {
+        ("search", [] | ["reset"]) => {
+            let search = Search::new(&memory(e));
+            let out = format!("{} candidates", search.candidates().len());
+            e.cheats.search = Some(search);
+            Ok(out)
+        }
+        ("search", _) => Filter::parse(args).and_then(|filter| {
+            match (e.cheats.search.take(), filter) {
+                (Some(search), _) => Ok(search),
+                // a value can be searched for without a snapshot
+                (None, Filter::Equal(_)) => Ok(Search::new(&memory(e))),
+                (None, _) => Err("take a snapshot first with 'search'".to_string()),
+            }
+            .map(|mut search| {
+                search.filter(&memory(e), filter);
+                let out = list_candidates(e, &search);
+                e.cheats.search = Some(search);
+                out
+            })
+        }),
+        ("candidates", []) => match &e.cheats.search {
+            Some(search) => Ok(list_candidates(e, search)),
+            None => Err("no search, start one with 'search'".to_string()),
+        },
+        ("freeze", [addr, rest @ ..]) => parse_address(addr).map(|addr| {
+            let (value, name) = match rest.split_first() {
+                Some((value, name)) => match parse_value(value) {
+                    Ok(value) => (value, name.join(" ")),
+                    // no value, the name starts right after the address
+                    Err(_) => (e.peek(addr as usize), rest.join(" ")),
+                },
+                None => (e.peek(addr as usize), String::new()),
+            };
+            e.cheats.freeze(Freeze { addr, value, name });
+            apply(e);
+            save(e, format!("froze 0x{:03X} at 0x{:02X}", addr, value))
+        }),
+        ("unfreeze", [addr]) => parse_address(addr).and_then(|addr| {
+            if e.cheats.unfreeze(addr) {
+                Ok(save(e, format!("unfroze 0x{:03X}", addr)))
+            } else {
+                Err(format!("0x{:03X} is not frozen", addr))
+            }
+        }),
+        ("cheats", []) => Ok(e
+            .cheats
+            .freezes
+            .iter()
+            .map(|f| format!("0x{:03X} = 0x{:02X} {}", f.addr, f.value, f.name))
+            .map(|s| s.trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")),
+        ("poke", [addr, value]) => parse_address(addr).and_then(|addr| {
+            let value = parse_value(value)?;
+            e.poke(addr as usize, value);
+            Ok(format!("0x{:03X} = 0x{:02X}", addr, value))
+        }),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_narrows_down_candidates() {
+        let mut mem = vec![0u8; 8];
+        mem[3] = 3;
+        mem[5] = 3;
+        let mut s = Search::new(&mem);
+        assert_eq!(s.candidates().len(), 8);
+        s.filter(&mem, Filter::Equal(3));
+        assert_eq!(s.candidates(), [3, 5]);
+        mem[3] = 2;
+        s.filter(&mem, Filter::Decreased);
+        assert_eq!(s.candidates(), [3]);
+        s.filter(&mem, Filter::Unchanged);
+        assert_eq!(s.candidates(), [3]);
+        s.filter(&mem, Filter::Changed);
+        assert!(s.candidates().is_empty());
+    }
+
+    #[test]
+    fn search_covers_64k_of_memory() {
+        let mut mem = vec![0u8; 0x10000];
+        mem[0xffff] = 7;
+        let mut s = Search::new(&mem);
+        assert_eq!(s.candidates().len(), 0x10000);
+        s.filter(&mem, Filter::Equal(7));
+        assert_eq!(s.candidates(), [0xffff]);
+    }
+
+    #[test]
+    fn cheats_parse_and_apply() {
+        let freezes = parse("# lives\n0x3F0 0x05 lives left\n\n512 7\n").unwrap();
+        assert_eq!(
+            freezes,
+            [
+                Freeze {
+                    addr: 0x3f0,
+                    value: 5,
+                    name: "lives left".to_string()
+                },
+                Freeze {
+                    addr: 0x200,
+                    value: 7,
+                    name: String::new()
+                },
+            ]
+        );
+        let mut e = Emulator::new();
+        e.cheats.freezes = freezes;
+        apply(&mut e);
+        assert_eq!((e.mem[0x3f0], e.mem[0x200]), (5, 7));
+        assert_eq!(
+            parse("0x3F0 0x100"),
+            Err("line 1: invalid value '0x100'".to_string())
+        );
+        assert!(parse("0x3F0").is_err());
+    }
+
+    #[test]
+    fn cheats_save_and_load() {
+        let path = std::env::temp_dir().join(format!("chip8-cheats-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut cheats = Cheats::load(&path).unwrap();
+        assert!(cheats.freezes.is_empty());
+        cheats.freeze(Freeze {
+            addr: 0x3f0,
+            value: 9,
+            name: "lives".to_string(),
+        });
+        cheats.save().unwrap();
+        let loaded = Cheats::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.freezes, cheats.freezes);
+    }
+
+    #[test]
+    fn cheat_commands() {
+        let mut e = Emulator::new();
+        // count down V0 from 3 into 0x300 every frame
+        e.load_rom(&[0x60, 0x03, 0xa3, 0x00, 0xf0, 0x55, 0x70, 0xff, 0x12, 0x04]);
+        e.ipf = 3;
+        e.run_frame();
+        let run = |e: &mut Emulator, line: &str| {
+            let mut words = line.split_whitespace();
+            let cmd = words.next().unwrap();
+            let args: Vec<&str> = words.collect();
+            execute(e, cmd, &args).unwrap().unwrap_or_else(|err| err)
+        };
+        assert_eq!(
+            run(&mut e, "search changed"),
+            "take a snapshot first with 'search'"
+        );
+        assert!(run(&mut e, "search eq 3").contains("\n0x300 = 0x03"));
+        e.run_frame();
+        let out = run(&mut e, "search decreased");
+        assert_eq!(out, "1 candidates\n0x300 = 0x02");
+        assert_eq!(run(&mut e, "freeze 0x300 lives"), "froze 0x300 at 0x02");
+        assert_eq!(run(&mut e, "cheats"), "0x300 = 0x02 lives");
+        e.run_frame();
+        e.run_frame();
+        assert_eq!(e.mem[0x300], 0x02);
+        assert_eq!(run(&mut e, "unfreeze 0x300"), "unfroze 0x300");
+        assert_eq!(run(&mut e, "poke 0x300 0x09"), "0x300 = 0x09");
+        assert_eq!(e.mem[0x300], 0x09);
+
+        // writes go to the display where it is mapped
+        e.mem.map_display(0xf00, e.vmem.len());
+        e.vmem.take_dirty();
+        assert_eq!(run(&mut e, "freeze 0xF00 0x80"), "froze 0xF00 at 0x80");
+        assert!(e.vmem.get(0, 0) && e.vmem.take_dirty());
+        assert_eq!(e.mem[0xf00], 0);
+        // and searches read it there
+        run(&mut e, "search reset");
+        let out = run(&mut e, "search eq 0x80");
+        assert!(out.contains("\n0xF00 = 0x80"), "{}", out);
+        assert!(execute(&mut e, "frobnicate", &[]).is_none());
+    }
+}
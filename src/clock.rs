@@ -0,0 +1,181 @@
+//! Emulation speed: pause, frame advance, fast-forward and slow motion.
+//!
+//! The emulator always runs whole 60 Hz frames, timers included, so changing
+//! the speed changes how many frames run per second of host time and the game
+//! behaves the same at any speed.
+
+use crate::FRAME_TIME;
+use std::time::{Duration, Instant};
+
+/// Most frames of host time caught up at once. After a longer stall, e.g.
+/// while the window is dragged or the machine is suspended, the rest is
+/// dropped instead of fast-forwarding the game through it.
+const MAX_CATCH_UP: u32 = 4;
+
+pub struct Clock {
+    pub paused: bool,
+    pub fast_forward: bool,
+    pub slow_motion: bool,
+    /// Speed while fast-forwarding.
+    fast_factor: u32,
+    /// Speed divisor in slow motion.
+    slow_factor: u32,
+    /// Emulated time owed but not run yet, less than a frame.
+    pending: Duration,
+    /// Frames to run while paused.
+    advance: u32,
+}
+
+impl Clock {
+    pub fn new(fast_factor: u32, slow_factor: u32) -> Self {
+        Clock {
+            paused: false,
+            fast_forward: false,
+            slow_motion: false,
+            fast_factor,
+            slow_factor,
+            pending: Duration::ZERO,
+            advance: 0,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advance = 0;
+    }
+
+    /// Run a single frame, pausing first when running.
+    pub fn advance(&mut self) {
+        self.paused = true;
+        self.advance += 1;
+    }
+
+    /// Number of frames to run for `elapsed` host time.
+    pub fn frames(&mut self, elapsed: Duration) -> u32 {
+        if self.paused {
+            self.pending = Duration::ZERO;
+            return std::mem::take(&mut self.advance);
+        }
+        let elapsed = elapsed.min(FRAME_TIME * MAX_CATCH_UP);
+        self.pending += if self.fast_forward {
+            elapsed * self.fast_factor
+        } else if self.slow_motion {
+            elapsed / self.slow_factor
+        } else {
+            elapsed
+        };
+        let frames = (self.pending.as_nanos() / FRAME_TIME.as_nanos()) as u32;
+        self.pending -= FRAME_TIME * frames;
+        frames
+    }
+
+    /// Shown in the window title when not running at normal speed.
+    pub fn status(&self) -> Option<String> {
+        if self.paused {
+            Some("paused".to_string())
+        } else if self.fast_forward {
+            Some(format!("fast-forward x{}", self.fast_factor))
+        } else if self.slow_motion {
+            Some(format!("slow motion 1/{}", self.slow_factor))
+        } else {
+            None
+        }
+    }
+}
+
+/// Frames run per second of host time, measured over a second at a time.
+pub struct FrameRate {
+    since: Instant,
+    frames: u32,
+    fps: Option<u32>,
+}
+
+impl FrameRate {
+    pub fn new(now: Instant) -> Self {
+        FrameRate {
+            since: now,
+            frames: 0,
+            fps: None,
+        }
+    }
+
+    /// Count `frames` run by `now`.
+    pub fn count(&mut self, frames: u32, now: Instant) {
+        self.frames += frames;
+        let elapsed = now.duration_since(self.since);
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = Some((self.frames as f64 / elapsed.as_secs_f64()).round() as u32);
+            self.frames = 0;
+            self.since = now;
+        }
+    }
+
+    /// Rate over the last second, once a second has passed.
+    pub fn fps(&self) -> Option<u32> {
+        self.fps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_runs_frames_at_the_chosen_speed() {
+        let mut c = Clock::new(4, 2);
+        assert_eq!(c.frames(FRAME_TIME * 3), 3);
+        assert_eq!(c.frames(FRAME_TIME / 2), 0);
+        assert_eq!(c.frames(FRAME_TIME / 2), 1);
+        assert_eq!(c.status(), None);
+
+        c.fast_forward = true;
+        assert_eq!(c.frames(FRAME_TIME * 3), 12);
+        assert_eq!(c.status().unwrap(), "fast-forward x4");
+        c.fast_forward = false;
+
+        c.slow_motion = true;
+        assert_eq!(c.frames(FRAME_TIME * 3), 1);
+        assert_eq!(c.frames(FRAME_TIME), 1);
+        assert_eq!(c.status().unwrap(), "slow motion 1/2");
+    }
+
+    #[test]
+    fn clock_drops_frames_after_a_stall() {
+        let mut c = Clock::new(4, 2);
+        assert_eq!(c.frames(Duration::from_secs(5)), MAX_CATCH_UP);
+        assert_eq!(c.frames(FRAME_TIME), 1);
+        c.fast_forward = true;
+        assert_eq!(c.frames(Duration::from_secs(5)), MAX_CATCH_UP * 4);
+    }
+
+    #[test]
+    fn clock_pauses_and_advances_frames() {
+        let mut c = Clock::new(4, 2);
+        c.toggle_pause();
+        assert_eq!(c.frames(FRAME_TIME * 3), 0);
+        assert_eq!(c.status().unwrap(), "paused");
+        c.advance();
+        c.advance();
+        assert_eq!(c.frames(FRAME_TIME * 3), 2);
+        assert_eq!(c.frames(FRAME_TIME * 3), 0);
+        c.toggle_pause();
+        assert_eq!(c.frames(FRAME_TIME), 1);
+
+        // advancing while running pauses
+        c.advance();
+        assert!(c.paused);
+        assert_eq!(c.frames(FRAME_TIME * 3), 1);
+    }
+
+    #[test]
+    fn frame_rate_is_measured_every_second() {
+        let start = Instant::now();
+        let mut rate = FrameRate::new(start);
+        rate.count(30, start + Duration::from_millis(500));
+        assert_eq!(rate.fps(), None);
+        rate.count(30, start + Duration::from_millis(1000));
+        assert_eq!(rate.fps(), Some(60));
+        rate.count(45, start + Duration::from_millis(2500));
+        assert_eq!(rate.fps(), Some(30));
+    }
+}
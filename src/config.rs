@@ -0,0 +1,28 @@
+//! Per user files kept between runs, such as cheats saved for a ROM.
+
+use std::env;
+use std::path::PathBuf;
+
+/// `$XDG_CONFIG_HOME/chip8-rust`, falling back to `~/.config/chip8-rust`.
+pub fn dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("chip8-rust"))
+}
+
+/// SHA-1 of a ROM as lower case hex, identifying it regardless of file name.
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_hash_is_sha1() {
+        assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+}
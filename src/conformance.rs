@@ -0,0 +1,247 @@
+//! Runs test ROMs headlessly and compares the final display with the golden
+//! images in `tests/golden`.
+//!
+//! Run `CHIP8_BLESS=1 cargo test conformance` to write the current output as the
+//! new golden images, and the test ROMs assembled from `rom/src`, and review the
+//! result with `git diff tests/golden`.
+
+use crate::quirks::Quirks;
+use crate::{asm, Emulator, ROM_START, WIDTH};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+struct Case {
+    name: &'static str,
+    rom: &'static str,
+    frames: u32,
+    quirks: Quirks,
+    /// Keys pressed or released at the start of a frame: (frame, key, pressed).
+    keys: &'static [(u32, u8, bool)],
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.txt", name))
+}
+
+fn to_ascii(pixels: &[u8]) -> String {
+    let mut out = String::new();
+    for row in pixels.chunks(WIDTH as usize) {
+        out.extend(row.iter().map(|p| if *p != 0 { '#' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+/// Render the expected and actual displays side by side, followed by a column
+/// marking pixels only lit in the actual output with `+` and pixels only lit in
+/// the expected output with `-`.
+fn diff(expected: &str, actual: &str) -> String {
+    let mut out = format!(
+        "{:w$} {:w$} diff\n",
+        "expected",
+        "actual",
+        w = WIDTH as usize
+    );
+    let mut differing = 0;
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    for (y, exp) in expected.lines().enumerate() {
+        let act = actual_lines.get(y).copied().unwrap_or("");
+        let marks: String = exp
+            .chars()
+            .zip(act.chars())
+            .map(|(e, a)| match (e, a) {
+                ('#', '.') => '-',
+                ('.', '#') => '+',
+                (e, _) => e,
+            })
+            .collect();
+        differing += marks.chars().filter(|c| *c == '+' || *c == '-').count();
+        out += &format!("{} {} {}\n", exp, act, marks);
+    }
+    out + &format!("{} pixels differ\n", differing)
+}
+
+fn check(case: &Case) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(case.rom);
+    let rom = fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let mut e = Emulator::new();
+    e.rng = StdRng::seed_from_u64(0);
+    e.quirks = case.quirks;
+    e.load_rom(&rom);
+    for frame in 0..case.frames {
+        for (_, key, pressed) in case.keys.iter().filter(|k| k.0 == frame) {
+            e.set_key_state(*key, *pressed);
+        }
+        e.run_frame();
+    }
+    let actual = to_ascii(&e.vmem.to_pixels());
+
+    let golden = golden_path(case.name);
+    if env::var_os("CHIP8_BLESS").is_some() {
+        fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        fs::write(&golden, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden).unwrap_or_else(|e| {
+        panic!(
+            "{}: {}, run with CHIP8_BLESS=1 to create it",
+            golden.display(),
+            e
+        )
+    });
+    if expected != actual {
+        panic!(
+            "display of {} after {} frames does not match {}\n{}",
+            case.rom,
+            case.frames,
+            golden.display(),
+            diff(&expected, &actual)
+        );
+    }
+}
+
+#[test]
+fn conformance_ibm_logo() {
+    check(&Case {
+        name: "ibm_logo",
+        rom: "rom/ibm_logo.ch8",
+        frames: 60,
+        quirks: Quirks::default(),
+        keys: &[],
+    });
+}
+
+#[test]
+fn conformance_test_opcode() {
+    check(&Case {
+        name: "test_opcode",
+        rom: "rom/test_opcode.ch8",
+        frames: 120,
+        quirks: Quirks::default(),
+        keys: &[],
+    });
+}
+
+/// The flags, quirks and keypad ROMs are built from the sources in `rom/src`,
+/// which also describe what they draw.
+#[test]
+fn conformance_test_roms_match_their_source() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("rom");
+    for name in ["test_flags", "test_quirks", "test_keypad"] {
+        let source = dir.join("src").join(format!("{}.asm", name));
+        let source =
+            fs::read_to_string(&source).unwrap_or_else(|e| panic!("{}: {}", source.display(), e));
+        let built = asm::assemble(&source, ROM_START as u16)
+            .unwrap_or_else(|e| panic!("{}.asm: {}", name, e));
+        let rom = dir.join(format!("{}.ch8", name));
+        if env::var_os("CHIP8_BLESS").is_some() {
+            fs::write(&rom, &built).unwrap();
+            continue;
+        }
+        let expected = fs::read(&rom).unwrap_or_else(|e| panic!("{}: {}", rom.display(), e));
+        assert!(
+            built == expected,
+            "{} differs from its source, run with CHIP8_BLESS=1 to rebuild it",
+            rom.display()
+        );
+    }
+}
+
+/// VF after `8XY4` without and with a carry (0 1), `8XY5` and `8XY7` without
+/// and with a borrow (1 0, 0 1), `8XY6` and `8XYE` shifting out a 1 (1 1), the
+/// same with VF as VX where the flag wins over the result (1 1) and `8XY1`,
+/// which leaves it at 7. Then drawing a sprite twice sets VF to 0 and 1.
+#[test]
+fn conformance_test_flags() {
+    check(&Case {
+        name: "test_flags",
+        rom: "rom/test_flags.ch8",
+        frames: 60,
+        quirks: Quirks::default(),
+        keys: &[],
+    });
+}
+
+/// Shows the shifted value (4 shifting VY, 1 with `shift`), the byte read by
+/// `FX65` after `FX55` (1 leaving I unchanged, 3 with `memoryIncrementByX`,
+/// 9 incrementing by X + 1), the `BNNN` target taken (1, or 2 with `jump`) and
+/// VF after `8XY1` (5, or 0 with `logic`). Then an 8 drawn at x = 62 on row 20
+/// wraps to the left edge or is clipped.
+fn check_quirks(name: &'static str, quirks: Quirks) {
+    check(&Case {
+        name,
+        rom: "rom/test_quirks.ch8",
+        frames: 60,
+        quirks,
+        keys: &[],
+    });
+}
+
+#[test]
+fn conformance_test_quirks() {
+    check_quirks("test_quirks", Quirks::default());
+}
+
+#[test]
+fn conformance_test_quirks_schip() {
+    check_quirks(
+        "test_quirks_schip",
+        Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: false,
+            jump: true,
+            logic: false,
+            ..Quirks::default()
+        },
+    );
+}
+
+#[test]
+fn conformance_test_quirks_vip() {
+    check_quirks(
+        "test_quirks_vip",
+        Quirks {
+            memory_leave_i_unchanged: false,
+            wrap: false,
+            logic: true,
+            ..Quirks::default()
+        },
+    );
+}
+
+/// Two `FX0A` waits get 3 and A, pressed and released in turn. `EX9E` waits
+/// for 5 to be held and `EXA1` for it to be let go, showing 5 and E. 7 is
+/// pressed before the last `FX0A` starts and only counts once released.
+#[test]
+fn conformance_test_keypad() {
+    check(&Case {
+        name: "test_keypad",
+        rom: "rom/test_keypad.ch8",
+        frames: 60,
+        quirks: Quirks::default(),
+        keys: &[
+            (2, 0x3, true),
+            (4, 0x3, false),
+            (6, 0xa, true),
+            (8, 0xa, false),
+            (10, 0x5, true),
+            (11, 0x7, true),
+            (12, 0x5, false),
+            (16, 0x7, false),
+        ],
+    });
+}
+
+#[test]
+fn conformance_diff_marks_differing_pixels() {
+    let d = diff("#.#.\n", "##..\n");
+    assert!(d.contains("#.#. ##.. #+-."), "{}", d);
+    assert!(d.ends_with("2 pixels differ\n"), "{}", d);
+}
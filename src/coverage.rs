@@ -0,0 +1,220 @@
+//! Tracks how every byte of memory is used while a ROM runs.
+//!
+//! Bytes are classified as executed code, sprite data read by `DXYN`, data read
+//! by `FX65` or `F002`, or data written by `FX33` and `FX55`. A byte can be used
+//! in several ways, for example a self-modifying ROM writes code it executes.
+
+use crate::capture;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+pub const EXECUTED: u8 = 1 << 0;
+pub const SPRITE: u8 = 1 << 1;
+pub const READ: u8 = 1 << 2;
+pub const WRITTEN: u8 = 1 << 3;
+
+const KINDS: [(u8, &str); 4] = [
+    (EXECUTED, "code"),
+    (SPRITE, "sprite"),
+    (READ, "read"),
+    (WRITTEN, "written"),
+];
+
+/// Width of the coverage image in bytes, one row is 64 bytes of memory.
+const IMAGE_WIDTH: usize = 64;
+const IMAGE_SCALE: usize = 8;
+
+pub struct Coverage {
+    flags: Vec<u8>,
+    rom_start: usize,
+    rom_len: usize,
+}
+
+impl Coverage {
+    pub fn new(mem_size: usize, rom_start: usize, rom_len: usize) -> Self {
+        Coverage {
+            flags: vec![0; mem_size],
+            rom_start,
+            rom_len,
+        }
+    }
+
+    /// Mark `len` bytes starting at `addr` as used in the given way.
+    pub fn mark(&mut self, addr: usize, len: usize, kind: u8) {
+        let size = self.flags.len();
+        for a in addr..addr + len {
+            self.flags[a % size] |= kind;
+        }
+    }
+
+    /// Size of the memory covered.
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn flags(&self, addr: usize) -> u8 {
+        self.flags[addr]
+    }
+
+    fn describe(flags: u8) -> String {
+        if flags == 0 {
+            return "untouched".to_string();
+        }
+        KINDS
+            .iter()
+            .filter(|(k, _)| flags & k != 0)
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Summary of the ROM bytes followed by the classification of every byte
+    /// in the ROM or touched outside of it, with consecutive bytes of the same
+    /// class merged into ranges.
+    pub fn report(&self) -> String {
+        let rom = &self.flags[self.rom_start..self.rom_start + self.rom_len];
+        let percent = |n: usize| {
+            if rom.is_empty() {
+                0.0
+            } else {
+                n as f64 * 100.0 / rom.len() as f64
+            }
+        };
+        let mut out = format!(
+            "ROM at 0x{:03X}-0x{:03X}, {} bytes\n",
+            self.rom_start,
+            self.rom_start + self.rom_len.max(1) - 1,
+            self.rom_len
+        );
+        for (kind, name) in KINDS {
+            let n = rom.iter().filter(|f| *f & kind != 0).count();
+            let _ = writeln!(out, "{:>9}: {:>5} bytes {:>5.1}%", name, n, percent(n));
+        }
+        let touched = rom.iter().filter(|f| **f != 0).count();
+        let _ = writeln!(
+            out,
+            "untouched: {:>5} bytes {:>5.1}%",
+            rom.len() - touched,
+            percent(rom.len() - touched)
+        );
+        let _ = writeln!(
+            out,
+            "  covered: {:>5} bytes {:>5.1}%\n",
+            touched,
+            percent(touched)
+        );
+
+        let in_rom = |a: usize| a >= self.rom_start && a < self.rom_start + self.rom_len;
+        let mut addr = 0;
+        while addr < self.flags.len() {
+            let flags = self.flags[addr];
+            let mut end = addr;
+            while end + 1 < self.flags.len()
+                && self.flags[end + 1] == flags
+                && in_rom(end + 1) == in_rom(addr)
+            {
+                end += 1;
+            }
+            if flags != 0 || in_rom(addr) {
+                let _ = writeln!(
+                    out,
+                    "0x{:03X}-0x{:03X} {}",
+                    addr,
+                    end,
+                    Coverage::describe(flags)
+                );
+            }
+            addr = end + 1;
+        }
+        out
+    }
+
+    /// Colour of a byte in the coverage image.
+    fn colour(&self, addr: usize) -> [u8; 4] {
+        let flags = self.flags(addr);
+        if flags & EXECUTED != 0 {
+            [0x3f, 0x7f, 0xff, 0xff]
+        } else if flags & SPRITE != 0 {
+            [0x3f, 0xdf, 0x5f, 0xff]
+        } else if flags & WRITTEN != 0 {
+            [0xef, 0x3f, 0x3f, 0xff]
+        } else if flags & READ != 0 {
+            [0xff, 0xd0, 0x3f, 0xff]
+        } else if addr >= self.rom_start && addr < self.rom_start + self.rom_len {
+            [0x60, 0x60, 0x60, 0xff]
+        } else {
+            [0x10, 0x10, 0x10, 0xff]
+        }
+    }
+
+    /// Write the map as an image with one 8x8 block per byte and 64 bytes per row.
+    /// Code is blue, sprites green, written data red, read data yellow, untouched
+    /// ROM bytes grey and the rest of memory black.
+    pub fn save_image(&self, path: &Path) -> io::Result<()> {
+        let height = self.flags.len() / IMAGE_WIDTH;
+        let mut rgba = Vec::with_capacity(self.flags.len() * IMAGE_SCALE * IMAGE_SCALE * 4);
+        for y in 0..height * IMAGE_SCALE {
+            for x in 0..IMAGE_WIDTH * IMAGE_SCALE {
+                let addr = (y / IMAGE_SCALE) * IMAGE_WIDTH + x / IMAGE_SCALE;
+                rgba.extend(self.colour(addr));
+            }
+        }
+        capture::write_png(path, &rgba, IMAGE_WIDTH * IMAGE_SCALE, height * IMAGE_SCALE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_marks_and_wraps() {
+        let mut c = Coverage::new(4096, 0x200, 4);
+        c.mark(0x200, 2, EXECUTED);
+        c.mark(0xfff, 2, READ);
+        assert_eq!(c.flags(0x201), EXECUTED);
+        assert_eq!(c.flags(0x202), 0);
+        assert_eq!(c.flags(0xfff), READ);
+        assert_eq!(c.flags(0x000), READ);
+    }
+
+    #[test]
+    fn coverage_report_summarises_the_rom() {
+        let mut c = Coverage::new(4096, 0x200, 8);
+        c.mark(0x200, 4, EXECUTED);
+        c.mark(0x204, 2, SPRITE);
+        c.mark(0x300, 3, WRITTEN | READ);
+        c.mark(0x50, 5, SPRITE);
+        let report = c.report();
+        assert!(
+            report.starts_with("ROM at 0x200-0x207, 8 bytes\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("     code:     4 bytes  50.0%"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("untouched:     2 bytes  25.0%"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("  covered:     6 bytes  75.0%"),
+            "{}",
+            report
+        );
+        assert!(report.contains("\n0x050-0x054 sprite\n"), "{}", report);
+        assert!(report.contains("\n0x200-0x203 code\n"), "{}", report);
+        assert!(report.contains("\n0x204-0x205 sprite\n"), "{}", report);
+        assert!(report.contains("\n0x206-0x207 untouched\n"), "{}", report);
+        assert!(
+            report.ends_with("\n0x300-0x302 read,written\n"),
+            "{}",
+            report
+        );
+    }
+}
@@ -0,0 +1,660 @@
+//! Breakpoints, memory watchpoints and the debugger command prompt.
+//!
+//! Breakpoints and watchpoints can carry a condition written in the expression
+//! language of [`crate::expr`], e.g. `break 0x2a0 if v3 == 5 && dt == 0`, and
+//! only stop when it is true. Each counts its hits and can ignore a number of
+//! them before stopping.
+
+use crate::cheat;
+use crate::disasm;
+use crate::expr::Expr;
+use crate::options::parse_address;
+use crate::Emulator;
+use std::fmt;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+pub const HELP: &str = "\
+commands:
+  b, break <ADDR> [if COND] stop before executing the instruction at ADDR
+  w, watch <ADDR[-ADDR]> [r|w|x|rw|rwx] [if COND]
+                            stop when memory is read, written or executed [default: w]
+  l, list                   list breakpoints and watchpoints
+  d, delete <bN|wN>         delete breakpoint or watchpoint N
+  ignore <bN|wN> <COUNT>    don't stop for the next COUNT hits
+  e, eval <EXPR>            evaluate an expression, e.g. mem[i + 1] * 2
+  c, continue               resume execution
+  p, pause                  pause execution
+  s, step                   execute a single instruction
+  r, regs                   show the registers
+  x <ADDR> [LEN]            dump memory
+  q, quit                   exit the emulator";
+
+/// How an instruction accesses memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Execute,
+    Read,
+    /// Read as sprite data by `DXYN`.
+    Sprite,
+    Write,
+}
+
+/// The condition and hit count of a breakpoint or watchpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trigger {
+    /// The condition as written and parsed.
+    pub condition: Option<(String, Expr)>,
+    /// Number of times the condition was true.
+    pub hits: u64,
+    /// Number of hits to ignore before stopping.
+    pub ignore: u64,
+}
+
+impl Trigger {
+    /// Split `SPEC if COND` into the spec and a trigger with the condition.
+    fn parse(s: &str) -> Result<(&str, Trigger), String> {
+        let (spec, condition) = match s.split_once(" if ") {
+            Some((spec, cond)) => (spec, Some((cond.trim().to_string(), Expr::parse(cond)?))),
+            None => (s, None),
+        };
+        let trigger = Trigger {
+            condition,
+            ..Trigger::default()
+        };
+        Ok((spec.trim(), trigger))
+    }
+
+    /// Count a hit when the condition holds, returns whether to stop.
+    fn fire(&mut self, e: &Emulator) -> bool {
+        if let Some((_, expr)) = &self.condition {
+            if !expr.is_true(e) {
+                return false;
+            }
+        }
+        self.hits += 1;
+        if self.ignore > 0 {
+            self.ignore -= 1;
+            return false;
+        }
+        true
+    }
+
+    fn status(&self) -> String {
+        let mut out = format!(", hit {} times", self.hits);
+        if self.ignore > 0 {
+            let _ = write!(out, ", ignoring the next {}", self.ignore);
+        }
+        out
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.condition {
+            Some((source, _)) => write!(f, " if {}", source),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub trigger: Trigger,
+}
+
+impl Breakpoint {
+    /// Parse an address optionally followed by a condition, e.g. `0x2a0 if v0 == 1`.
+    pub fn parse(s: &str) -> Result<Breakpoint, String> {
+        let (addr, trigger) = Trigger::parse(s.trim())?;
+        Ok(Breakpoint {
+            addr: parse_address(addr)?,
+            trigger,
+        })
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:03X}{}", self.addr, self.trigger)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub trigger: Trigger,
+}
+
+impl Watchpoint {
+    /// Parse an address or range, optionally followed by the kind of access to
+    /// watch and a condition, e.g. `0x3f0`, `0x300-0x30f rw` or
+    /// `0x300:x if v0 > 2`.
+    pub fn parse(s: &str) -> Result<Watchpoint, String> {
+        let (s, trigger) = Trigger::parse(s.trim())?;
+        let (range, kinds) = match s.split_once(|c: char| c == ':' || c.is_whitespace()) {
+            Some((range, kinds)) => (range, kinds.trim()),
+            None => (s, "w"),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+            None => (parse_address(range)?, parse_address(range)?),
+        };
+        if start > end || kinds.is_empty() || kinds.chars().any(|c| !"rwx".contains(c)) {
+            return Err(format!("invalid watchpoint '{}'", s));
+        }
+        Ok(Watchpoint {
+            start,
+            end,
+            read: kinds.contains('r'),
+            write: kinds.contains('w'),
+            execute: kinds.contains('x'),
+            trigger,
+        })
+    }
+
+    pub fn matches(&self, addr: u16, access: Access) -> bool {
+        let kind = match access {
+            Access::Execute => self.execute,
+            Access::Read | Access::Sprite => self.read,
+            Access::Write => self.write,
+        };
+        kind && (self.start..=self.end).contains(&addr)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:03X}-0x{:03X} ", self.start, self.end)?;
+        for (set, c) in [(self.read, 'r'), (self.write, 'w'), (self.execute, 'x')] {
+            if set {
+                write!(f, "{}", c)?;
+            }
+        }
+        write!(f, "{}", self.trigger)
+    }
+}
+
+/// An access that triggered a watchpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: usize,
+    /// Address and opcode of the instruction making the access.
+    pub pc: u16,
+    pub instr: u16,
+    pub addr: u16,
+    pub access: Access,
+    pub old: u8,
+    pub new: u8,
+}
+
+/// Why the emulator is not running.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Paused,
+    Breakpoint(usize),
+    Watchpoint(WatchHit),
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub stop: Option<Stop>,
+    /// Address and opcode of the executing instruction.
+    current: (u16, u16),
+    /// Don't stop at a breakpoint on this address, so execution can resume
+    /// from a breakpoint.
+    resume_at: Option<u16>,
+}
+
+impl Debugger {
+    pub fn is_stopped(&self) -> bool {
+        self.stop.is_some()
+    }
+
+    pub fn pause(&mut self) {
+        if self.stop.is_none() {
+            self.stop = Some(Stop::Paused);
+        }
+    }
+
+    /// Resume execution from `pc`.
+    pub fn resume(&mut self, pc: u16) {
+        self.stop = None;
+        self.resume_at = Some(pc);
+    }
+
+    /// Whether there are breakpoints or watchpoints to check.
+    pub fn is_armed(&self) -> bool {
+        !self.breakpoints.is_empty() || !self.watchpoints.is_empty()
+    }
+
+    /// Check whether execution should stop before the next instruction of `e`.
+    pub fn should_break(&mut self, e: &Emulator) -> bool {
+        let pc = e.pc;
+        if self.resume_at == Some(pc) || !self.is_armed() {
+            return false;
+        }
+        if let Some(n) = self
+            .breakpoints
+            .iter_mut()
+            .position(|b| b.addr == pc && b.trigger.fire(e))
+        {
+            self.stop = Some(Stop::Breakpoint(n));
+            return true;
+        }
+        let instr = Emulator::read_word(&e.mem, pc);
+        for addr in [pc, pc.wrapping_add(1)] {
+            if let Some(n) = self
+                .watchpoints
+                .iter_mut()
+                .position(|w| w.matches(addr, Access::Execute) && w.trigger.fire(e))
+            {
+                self.stop = Some(Stop::Watchpoint(WatchHit {
+                    watchpoint: n,
+                    pc,
+                    instr,
+                    addr,
+                    access: Access::Execute,
+                    old: 0,
+                    new: 0,
+                }));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Called before an instruction executes.
+    pub fn begin(&mut self, pc: u16, instr: u16) {
+        self.current = (pc, instr);
+        self.resume_at = None;
+    }
+
+    /// Whether memory accesses need to be checked.
+    pub fn is_watching(&self) -> bool {
+        !self.watchpoints.is_empty() && self.stop.is_none()
+    }
+
+    /// Called for every memory access, `old` and `new` are the values before and
+    /// after the access. Conditions see the state after the access.
+    pub fn check_access(&mut self, e: &Emulator, addr: u16, access: Access, old: u8, new: u8) {
+        if !self.is_watching() || access == Access::Execute {
+            return;
+        }
+        if let Some(n) = self
+            .watchpoints
+            .iter_mut()
+            .position(|w| w.matches(addr, access) && w.trigger.fire(e))
+        {
+            self.stop = Some(Stop::Watchpoint(WatchHit {
+                watchpoint: n,
+                pc: self.current.0,
+                instr: self.current.1,
+                addr,
+                access,
+                old,
+                new,
+            }));
+        }
+    }
+
+    /// The trigger of a breakpoint or watchpoint named like `b0` or `w1`.
+    pub fn trigger(&mut self, id: &str) -> Option<&mut Trigger> {
+        let n: usize = id.get(1..)?.parse().ok()?;
+        match id.chars().next()? {
+            'b' => self.breakpoints.get_mut(n).map(|b| &mut b.trigger),
+            'w' => self.watchpoints.get_mut(n).map(|w| &mut w.trigger),
+            _ => None,
+        }
+    }
+
+    /// Describe why the emulator stopped.
+    pub fn describe_stop(&self, pc: u16) -> String {
+        match &self.stop {
+            None => format!("running at 0x{:03X}", pc),
+            Some(Stop::Paused) => format!("paused at 0x{:03X}", pc),
+            Some(Stop::Breakpoint(n)) => format!("breakpoint b{} at 0x{:03X}", n, pc),
+            Some(Stop::Watchpoint(hit)) => {
+                let what = match hit.access {
+                    Access::Execute => format!("is about to execute 0x{:03X}", hit.addr),
+                    Access::Read => format!("read 0x{:03X} = 0x{:02X}", hit.addr, hit.new),
+                    Access::Sprite => {
+                        format!("read sprite data 0x{:03X} = 0x{:02X}", hit.addr, hit.new)
+                    }
+                    Access::Write => format!(
+                        "wrote 0x{:03X}: 0x{:02X} -> 0x{:02X}",
+                        hit.addr, hit.old, hit.new
+                    ),
+                };
+                let watch = self
+                    .watchpoints
+                    .get(hit.watchpoint)
+                    .map(|w| w.to_string())
+                    .unwrap_or_default();
+                format!(
+                    "watchpoint w{} ({}): {} at 0x{:03X} {}",
+                    hit.watchpoint,
+                    watch,
+                    disasm::disassemble(hit.instr),
+                    hit.pc,
+                    what
+                )
+            }
+        }
+    }
+}
+
+fn registers(e: &Emulator) -> String {
+    let mut out = format!(
+        "PC=0x{:03X} I=0x{:03X} DT=0x{:02X} ST=0x{:02X} SP={}\n",
+        e.pc,
+        e.i,
+        e.dt,
+        e.st,
+        e.stack_depth()
+    );
+    for (n, v) in e.v.iter().enumerate() {
+        let _ = write!(
+            out,
+            "V{:X}=0x{:02X}{}",
+            n,
+            v,
+            match n {
+                7 => "\n",
+                15 => "",
+                _ => " ",
+            }
+        );
+    }
+    let instr = Emulator::read_word(&e.mem, e.pc);
+    let _ = write!(
+        out,
+        "\nnext: 0x{:03X}  {:04X}  {}",
+        e.pc,
+        instr,
+        disasm::disassemble(instr)
+    );
+    out
+}
+
+fn dump(e: &Emulator, start: u16, len: u16) -> String {
+    let mut out = String::new();
+    for row in (0..len).step_by(16) {
+        let addr = start.wrapping_add(row);
+        let _ = write!(out, "0x{:03X}:", addr);
+        for i in row..(row + 16).min(len) {
+            let a = start.wrapping_add(i) as usize % e.mem.len();
+            let _ = write!(out, " {:02X}", e.peek(a));
+        }
+        out.push('\n');
+    }
+    out.pop();
+    out
+}
+
+/// Describe the current stop followed by the registers.
+pub fn report(e: &Emulator) -> String {
+    format!("{}\n{}", e.debug.describe_stop(e.pc), registers(e))
+}
+
+pub fn is_quit(line: &str) -> bool {
+    matches!(line.trim(), "q" | "quit")
+}
+
+/// Read commands from stdin until execution resumes. Returns false on `quit` or
+/// at the end of input.
+pub fn prompt(e: &mut Emulator) -> bool {
+    let stdin = io::stdin();
+    loop {
+        print!("(chip8) ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return false,
+            Ok(_) if is_quit(&line) => return false,
+            Ok(_) => {}
+        }
+        let out = execute(e, &line);
+        if !out.is_empty() {
+            println!("{}", out);
+        }
+        if !e.debug.is_stopped() {
+            return true;
+        }
+    }
+}
+
+/// Run a debugger command and return its output.
+pub fn execute(e: &mut Emulator, line: &str) -> String {
+    let mut words = line.split_whitespace();
+    let cmd = words.next().unwrap_or("");
+    let args: Vec<&str> = words.collect();
+    let result = match (cmd, args.as_slice()) {
+        ("", _) => Ok(String::new()),
+        ("h" | "help", _) => Ok(format!("{}\n{}", HELP, cheat::HELP)),
+        ("b" | "break", [_, ..]) => Breakpoint::parse(&args.join(" ")).map(|b| {
+            let s = format!("b{} {}", e.debug.breakpoints.len(), b);
+            e.debug.breakpoints.push(b);
+            s
+        }),
+        ("w" | "watch", [_, ..]) => Watchpoint::parse(&args.join(" ")).map(|w| {
+            let s = format!("w{} {}", e.debug.watchpoints.len(), w);
+            e.debug.watchpoints.push(w);
+            s
+        }),
+        ("l" | "list", []) => {
+            let mut out = Vec::new();
+            for (n, b) in e.debug.breakpoints.iter().enumerate() {
+                out.push(format!("b{} {}{}", n, b, b.trigger.status()));
+            }
+            for (n, w) in e.debug.watchpoints.iter().enumerate() {
+                out.push(format!("w{} {}{}", n, w, w.trigger.status()));
+            }
+            Ok(out.join("\n"))
+        }
+        ("d" | "delete", [id]) => {
+            let n = id.get(1..).and_then(|n| n.parse::<usize>().ok());
+            match (id.chars().next(), n) {
+                (Some('b'), Some(n)) if n < e.debug.breakpoints.len() => {
+                    e.debug.breakpoints.remove(n);
+                    Ok(format!("deleted {}", id))
+                }
+                (Some('w'), Some(n)) if n < e.debug.watchpoints.len() => {
+                    e.debug.watchpoints.remove(n);
+                    Ok(format!("deleted {}", id))
+                }
+                _ => Err(format!("no breakpoint or watchpoint '{}'", id)),
+            }
+        }
+        ("ignore", [id, count]) => match (e.debug.trigger(id), count.parse::<u64>()) {
+            (Some(trigger), Ok(count)) => {
+                trigger.ignore = count;
+                Ok(format!("ignoring the next {} hits of {}", count, id))
+            }
+            (None, _) => Err(format!("no breakpoint or watchpoint '{}'", id)),
+            (_, Err(_)) => Err(format!("invalid count '{}'", count)),
+        },
+        ("e" | "eval", [_, ..]) => Expr::parse(&args.join(" ")).map(|expr| {
+            let value = expr.eval(e);
+            format!("{} (0x{:X})", value, value)
+        }),
+        ("c" | "continue", []) => {
+            e.debug.resume(e.pc);
+            Ok("continuing".to_string())
+        }
+        ("p" | "pause", []) => {
+            e.debug.pause();
+            Ok(report(e))
+        }
+        ("s" | "step", []) => {
+            e.debug.resume(e.pc);
+            e.step();
+            e.debug.pause();
+            Ok(report(e))
+        }
+        ("r" | "regs", []) => Ok(registers(e)),
+        ("x", [addr]) => parse_address(addr).map(|addr| dump(e, addr, 16)),
+        ("x", [addr, len]) => parse_address(addr)
+            .and_then(|addr| Ok((addr, parse_address(len)?)))
+            .map(|(addr, len)| dump(e, addr, len)),
+        _ => cheat::execute(e, cmd, &args)
+            .unwrap_or_else(|| Err(format!("unknown command '{}', try 'help'", line.trim()))),
+    };
+    result.unwrap_or_else(|err| err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchpoint_parses_ranges_and_kinds() {
+        let w = Watchpoint::parse("0x3f0").unwrap();
+        assert_eq!((w.start, w.end), (0x3f0, 0x3f0));
+        assert!(w.write && !w.read && !w.execute);
+
+        let w = Watchpoint::parse("0x300-0x30f rw").unwrap();
+        assert_eq!((w.start, w.end), (0x300, 0x30f));
+        assert!(w.write && w.read && !w.execute);
+        assert_eq!(w.to_string(), "0x300-0x30F rw");
+
+        assert!(Watchpoint::parse("0x300:x").unwrap().execute);
+        assert!(Watchpoint::parse("0x300:q").is_err());
+        assert!(Watchpoint::parse("0x30f-0x300").is_err());
+    }
+
+    #[test]
+    fn breakpoint_parses_conditions() {
+        let b = Breakpoint::parse("0x2a0").unwrap();
+        assert_eq!((b.addr, b.trigger.condition), (0x2a0, None));
+
+        let b = Breakpoint::parse("0x2a0 if v3 == 5 && i >= 0x300").unwrap();
+        assert_eq!(b.addr, 0x2a0);
+        assert_eq!(b.to_string(), "0x2A0 if v3 == 5 && i >= 0x300");
+        assert!(Breakpoint::parse("0x2a0 if v3 = 5").is_err());
+
+        let w = Watchpoint::parse("0x300-0x30f rw if mem[0x300] != 0").unwrap();
+        assert!(w.read && w.write);
+        assert_eq!(w.to_string(), "0x300-0x30F rw if mem[0x300] != 0");
+        assert!(Watchpoint::parse("0x300 if mem[0x300]").unwrap().write);
+    }
+
+    #[test]
+    fn debugger_stops_at_breakpoints_once_per_resume() {
+        let mut e = Emulator::new();
+        let mut d = Debugger::default();
+        d.breakpoints.push(Breakpoint::parse("0x204").unwrap());
+        e.pc = 0x202;
+        assert!(!d.should_break(&e));
+        e.pc = 0x204;
+        assert!(d.should_break(&e));
+        assert_eq!(d.stop, Some(Stop::Breakpoint(0)));
+        d.resume(0x204);
+        assert!(!d.should_break(&e));
+        d.begin(0x204, 0x1204);
+        assert!(d.should_break(&e));
+        assert_eq!(d.breakpoints[0].trigger.hits, 2);
+    }
+
+    #[test]
+    fn debugger_checks_conditions_and_hit_counts() {
+        let mut e = Emulator::new();
+        let mut d = Debugger::default();
+        d.breakpoints
+            .push(Breakpoint::parse("0x200 if v3 == 5").unwrap());
+        d.breakpoints[0].trigger.ignore = 1;
+        assert!(!d.should_break(&e));
+        assert_eq!(d.breakpoints[0].trigger.hits, 0);
+        e.v[3] = 5;
+        assert!(!d.should_break(&e));
+        assert!(d.should_break(&e));
+        assert_eq!(d.breakpoints[0].trigger.hits, 2);
+        assert_eq!(d.breakpoints[0].trigger.ignore, 0);
+    }
+
+    #[test]
+    fn debugger_reports_watched_accesses() {
+        let e = Emulator::new();
+        let mut d = Debugger::default();
+        d.watchpoints.push(Watchpoint::parse("0x3f0 w").unwrap());
+        d.begin(0x2a4, 0xf355);
+        d.check_access(&e, 0x3f0, Access::Read, 5, 5);
+        assert_eq!(d.stop, None);
+        d.check_access(&e, 0x3f0, Access::Write, 5, 4);
+        assert_eq!(
+            d.describe_stop(0x2a6),
+            "watchpoint w0 (0x3F0-0x3F0 w): LD [I], V3 at 0x2A4 wrote 0x3F0: 0x05 -> 0x04"
+        );
+    }
+
+    #[test]
+    fn debugger_commands() {
+        let mut e = Emulator::new();
+        e.load_rom(&[0x60, 0x01, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x06]);
+        assert_eq!(execute(&mut e, "break 0x204"), "b0 0x204");
+        assert_eq!(execute(&mut e, "w 0x300-0x301 rw"), "w0 0x300-0x301 rw");
+
+        e.run_frame();
+        assert_eq!(e.pc, 0x204);
+        assert_eq!(e.debug.stop, Some(Stop::Breakpoint(0)));
+        assert_eq!(
+            execute(&mut e, "list"),
+            "b0 0x204, hit 1 times\nw0 0x300-0x301 rw, hit 0 times"
+        );
+
+        let out = execute(&mut e, "step");
+        assert!(
+            out.starts_with(
+                "watchpoint w0 (0x300-0x301 rw): LD [I], V0 at 0x204 wrote 0x300: 0x00 -> 0x01"
+            ),
+            "{}",
+            out
+        );
+        assert_eq!(e.pc, 0x206);
+        assert_eq!(execute(&mut e, "x 0x300 2"), "0x300: 01 00");
+        assert_eq!(execute(&mut e, "delete w0"), "deleted w0");
+        assert_eq!(
+            execute(&mut e, "delete w0"),
+            "no breakpoint or watchpoint 'w0'"
+        );
+        execute(&mut e, "continue");
+        e.run_frame();
+        assert_eq!(e.debug.stop, None);
+        assert!(execute(&mut e, "frobnicate").starts_with("unknown command"));
+    }
+
+    #[test]
+    fn debugger_commands_with_conditions() {
+        let mut e = Emulator::new();
+        // count V0 up in a loop
+        e.load_rom(&[0x70, 0x01, 0x12, 0x00]);
+        assert_eq!(
+            execute(&mut e, "break 0x202 if v0 >= 3"),
+            "b0 0x202 if v0 >= 3"
+        );
+        assert_eq!(
+            execute(&mut e, "ignore b0 2"),
+            "ignoring the next 2 hits of b0"
+        );
+        assert_eq!(
+            execute(&mut e, "list"),
+            "b0 0x202 if v0 >= 3, hit 0 times, ignoring the next 2"
+        );
+        e.run_frame();
+        assert_eq!(e.debug.stop, Some(Stop::Breakpoint(0)));
+        assert_eq!(execute(&mut e, "eval v0 * 2 + 1"), "11 (0xB)");
+        assert_eq!(execute(&mut e, "eval v0 +"), "unexpected end of expression");
+        assert_eq!(
+            execute(&mut e, "ignore b1 2"),
+            "no breakpoint or watchpoint 'b1'"
+        );
+        assert_eq!(execute(&mut e, "ignore b0 x"), "invalid count 'x'");
+    }
+}
@@ -0,0 +1,161 @@
+//! Instructions decoded once into an `Op` and cached per address, so the hot
+//! loop doesn't take every opcode apart again.
+
+/// A decoded instruction. `x` and `y` are register numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Cls,
+    Ret,
+    /// `0NNN`, a machine code subroutine.
+    Sys(u16),
+    Jump(u16),
+    Call(u16),
+    SkipEqByte(usize, u8),
+    SkipNeByte(usize, u8),
+    SkipEq(usize, usize),
+    SkipNe(usize, usize),
+    SetByte(usize, u8),
+    AddByte(usize, u8),
+    Set(usize, usize),
+    /// `8XY1`, `8XY2` and `8XY3`, with the operation in the last nibble.
+    Logic(usize, usize, u8),
+    Add(usize, usize),
+    Sub(usize, usize),
+    SubN(usize, usize),
+    ShiftRight(usize, usize),
+    ShiftLeft(usize, usize),
+    SetI(u16),
+    /// `BNNN`, with X kept for the jump quirk.
+    JumpOffset(usize, u16),
+    Random(usize, u8),
+    Draw(usize, usize, u8),
+    SkipKey(usize),
+    SkipNotKey(usize),
+    GetDelay(usize),
+    SetDelay(usize),
+    SetSound(usize),
+    Audio,
+    Pitch(usize),
+    WaitKey(usize),
+    Bcd(usize),
+    Font(usize),
+    Store(usize),
+    LoadRegs(usize),
+    AddI(usize),
+    Unknown(u16),
+}
+
+pub fn decode(instr: u16) -> Op {
+    let t = (instr & 0xf000) >> 12;
+    let x = ((instr & 0x0f00) >> 8) as usize;
+    let y = ((instr & 0x00f0) >> 4) as usize;
+    let n = (instr & 0x000f) as u8;
+    let nn = (instr & 0x00ff) as u8;
+    let nnn = instr & 0x0fff;
+    match (t, x, y, n) {
+        (0x0, 0x0, 0xe, 0x0) => Op::Cls,
+        (0x0, 0x0, 0xe, 0xe) => Op::Ret,
+        (0x0, _, _, _) => Op::Sys(nnn),
+        (0x1, _, _, _) => Op::Jump(nnn),
+        (0x2, _, _, _) => Op::Call(nnn),
+        (0x3, _, _, _) => Op::SkipEqByte(x, nn),
+        (0x4, _, _, _) => Op::SkipNeByte(x, nn),
+        (0x5, _, _, _) => Op::SkipEq(x, y),
+        (0x6, _, _, _) => Op::SetByte(x, nn),
+        (0x7, _, _, _) => Op::AddByte(x, nn),
+        (0x8, _, _, 0x0) => Op::Set(x, y),
+        (0x8, _, _, 0x1..=0x3) => Op::Logic(x, y, n),
+        (0x8, _, _, 0x4) => Op::Add(x, y),
+        (0x8, _, _, 0x5) => Op::Sub(x, y),
+        (0x8, _, _, 0x6) => Op::ShiftRight(x, y),
+        (0x8, _, _, 0x7) => Op::SubN(x, y),
+        (0x8, _, _, 0xe) => Op::ShiftLeft(x, y),
+        (0x9, _, _, _) => Op::SkipNe(x, y),
+        (0xa, _, _, _) => Op::SetI(nnn),
+        (0xb, _, _, _) => Op::JumpOffset(x, nnn),
+        (0xc, _, _, _) => Op::Random(x, nn),
+        (0xd, _, _, _) => Op::Draw(x, y, n),
+        (0xe, _, 0x9, 0xe) => Op::SkipKey(x),
+        (0xe, _, 0xa, 0x1) => Op::SkipNotKey(x),
+        (0xf, _, 0x0, 0x7) => Op::GetDelay(x),
+        (0xf, _, 0x1, 0x5) => Op::SetDelay(x),
+        (0xf, _, 0x1, 0x8) => Op::SetSound(x),
+        (0xf, 0x0, 0x0, 0x2) => Op::Audio,
+        (0xf, _, 0x3, 0xa) => Op::Pitch(x),
+        (0xf, _, 0x0, 0xa) => Op::WaitKey(x),
+        (0xf, _, 0x3, 0x3) => Op::Bcd(x),
+        (0xf, _, 0x2, 0x9) => Op::Font(x),
+        (0xf, _, 0x5, 0x5) => Op::Store(x),
+        (0xf, _, 0x6, 0x5) => Op::LoadRegs(x),
+        (0xf, _, 0x1, 0xe) => Op::AddI(x),
+        _ => Op::Unknown(instr),
+    }
+}
+
+/// Decoded instructions by address. Writes through the emulator invalidate
+/// the bytes they change, and an entry is only used while memory still holds
+/// the opcode it was decoded from, so self-modifying code and writes that
+/// bypass the emulator, such as the debugger's, are seen too.
+pub struct DecodeCache {
+    entries: Vec<Option<(u16, Op)>>,
+}
+
+impl DecodeCache {
+    pub fn new(mem_size: usize) -> Self {
+        DecodeCache {
+            entries: vec![None; mem_size],
+        }
+    }
+
+    /// The decoded form of `instr`, fetched from `addr`.
+    pub fn get(&mut self, addr: u16, instr: u16) -> Op {
+        let len = self.entries.len();
+        let entry = &mut self.entries[addr as usize % len];
+        match *entry {
+            Some((cached, op)) if cached == instr => op,
+            _ => {
+                let op = decode(instr);
+                *entry = Some((instr, op));
+                op
+            }
+        }
+    }
+
+    /// Forget the instructions overlapping a written byte.
+    pub fn invalidate(&mut self, addr: usize) {
+        let len = self.entries.len();
+        self.entries[addr % len] = None;
+        self.entries[(addr + len - 1) % len] = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_splits_opcodes() {
+        assert_eq!(decode(0x00e0), Op::Cls);
+        assert_eq!(decode(0x1228), Op::Jump(0x228));
+        assert_eq!(decode(0x8122), Op::Logic(1, 2, 2));
+        assert_eq!(decode(0xb2fd), Op::JumpOffset(2, 0x2fd));
+        assert_eq!(decode(0xd015), Op::Draw(0, 1, 5));
+        assert_eq!(decode(0xf565), Op::LoadRegs(5));
+        assert_eq!(decode(0x0123), Op::Sys(0x123));
+        assert_eq!(decode(0x5121), Op::SkipEq(1, 2));
+        assert_eq!(decode(0xe1ff), Op::Unknown(0xe1ff));
+    }
+
+    #[test]
+    fn decode_cache_notices_changed_code() {
+        let mut cache = DecodeCache::new(4096);
+        assert_eq!(cache.get(0x200, 0x6321), Op::SetByte(3, 0x21));
+        assert_eq!(cache.get(0x200, 0x6321), Op::SetByte(3, 0x21));
+        assert_eq!(cache.get(0x200, 0x7321), Op::AddByte(3, 0x21));
+        cache.invalidate(0x201);
+        assert_eq!(cache.entries[0x200], None);
+        cache.get(0xfff, 0x00e0);
+        cache.invalidate(0);
+        assert_eq!(cache.entries[0xfff], None);
+    }
+}
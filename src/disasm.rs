@@ -0,0 +1,100 @@
+use crate::decode::{decode, Op};
+
+/// Disassemble an instruction using the mnemonics from Cowgod's technical
+/// reference. It is decoded the way it executes, so e.g. `5XY1` shows as the
+/// skip it runs as.
+pub fn disassemble(instr: u16) -> String {
+    match decode(instr) {
+        Op::Cls => "CLS".to_string(),
+        Op::Ret => "RET".to_string(),
+        Op::Sys(nnn) => format!("SYS 0x{:03X}", nnn),
+        Op::Jump(nnn) => format!("JP 0x{:03X}", nnn),
+        Op::Call(nnn) => format!("CALL 0x{:03X}", nnn),
+        Op::SkipEqByte(x, nn) => format!("SE V{:X}, 0x{:02X}", x, nn),
+        Op::SkipNeByte(x, nn) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        Op::SkipEq(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        Op::SetByte(x, nn) => format!("LD V{:X}, 0x{:02X}", x, nn),
+        Op::AddByte(x, nn) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        Op::Set(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Op::Logic(x, y, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        Op::Logic(x, y, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        Op::Logic(x, y, _) => format!("XOR V{:X}, V{:X}", x, y),
+        Op::Add(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Op::Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        Op::ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        Op::SubN(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        Op::ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        Op::SkipNe(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        Op::SetI(nnn) => format!("LD I, 0x{:03X}", nnn),
+        Op::JumpOffset(_, nnn) => format!("JP V0, 0x{:03X}", nnn),
+        Op::Random(x, nn) => format!("RND V{:X}, 0x{:02X}", x, nn),
+        Op::Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Op::SkipKey(x) => format!("SKP V{:X}", x),
+        Op::SkipNotKey(x) => format!("SKNP V{:X}", x),
+        Op::Audio => "AUDIO".to_string(),
+        Op::GetDelay(x) => format!("LD V{:X}, DT", x),
+        Op::WaitKey(x) => format!("LD V{:X}, K", x),
+        Op::SetDelay(x) => format!("LD DT, V{:X}", x),
+        Op::SetSound(x) => format!("LD ST, V{:X}", x),
+        Op::AddI(x) => format!("ADD I, V{:X}", x),
+        Op::Font(x) => format!("LD F, V{:X}", x),
+        Op::Bcd(x) => format!("LD B, V{:X}", x),
+        Op::Pitch(x) => format!("PITCH V{:X}", x),
+        Op::Store(x) => format!("LD [I], V{:X}", x),
+        Op::LoadRegs(x) => format!("LD V{:X}, [I]", x),
+        Op::Unknown(instr) => format!("DW 0x{:04X}", instr),
+    }
+}
+
+/// The mnemonic of an instruction, e.g. `DRW` for `DXYN`.
+pub fn mnemonic(instr: u16) -> String {
+    let mut s = disassemble(instr);
+    s.truncate(s.find(' ').unwrap_or(s.len()));
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_flow_control() {
+        assert_eq!(disassemble(0x00e0), "CLS");
+        assert_eq!(disassemble(0x00ee), "RET");
+        assert_eq!(disassemble(0x0123), "SYS 0x123");
+        assert_eq!(disassemble(0x1228), "JP 0x228");
+        assert_eq!(disassemble(0x2abc), "CALL 0xABC");
+        assert_eq!(disassemble(0xb2fd), "JP V0, 0x2FD");
+    }
+
+    #[test]
+    fn disassemble_registers() {
+        assert_eq!(disassemble(0x3a0f), "SE VA, 0x0F");
+        assert_eq!(disassemble(0x6321), "LD V3, 0x21");
+        assert_eq!(disassemble(0x8124), "ADD V1, V2");
+        assert_eq!(disassemble(0x801e), "SHL V0, V1");
+        assert_eq!(disassemble(0xd015), "DRW V0, V1, 5");
+        assert_eq!(disassemble(0xf565), "LD V5, [I]");
+        assert_eq!(disassemble(0xf002), "AUDIO");
+    }
+
+    #[test]
+    fn disassemble_unknown_instructions_as_data() {
+        assert_eq!(disassemble(0x8128), "DW 0x8128");
+        assert_eq!(disassemble(0xffff), "DW 0xFFFF");
+    }
+
+    #[test]
+    fn disassemble_instructions_as_they_execute() {
+        // the last nibble of 5XYN and 9XYN is ignored
+        assert_eq!(disassemble(0x5121), "SE V1, V2");
+        assert_eq!(disassemble(0x912f), "SNE V1, V2");
+        assert_eq!(disassemble(0xb2fd), "JP V0, 0x2FD");
+    }
+
+    #[test]
+    fn mnemonic_is_the_first_word() {
+        assert_eq!(mnemonic(0xd015), "DRW");
+        assert_eq!(mnemonic(0x00e0), "CLS");
+    }
+}
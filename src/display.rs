@@ -0,0 +1,162 @@
+//! Scaling the display to the window and remembering the window geometry.
+
+use crate::config;
+use crate::framebuffer::Framebuffer;
+use crate::palette::Palette;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// How the display is scaled to fill the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scaling {
+    /// Whole multiples of the display size, centred with borders.
+    Integer,
+    /// As large as fits while keeping the aspect ratio, centred with borders.
+    Fit,
+    /// Fill the whole window, ignoring the aspect ratio.
+    Stretch,
+}
+
+impl Scaling {
+    pub fn parse(s: &str) -> Result<Scaling, String> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            "stretch" => Ok(Scaling::Stretch),
+            _ => Err(format!(
+                "unknown scaling '{}', expected integer, fit or stretch",
+                s
+            )),
+        }
+    }
+
+    pub fn next(self) -> Scaling {
+        match self {
+            Scaling::Integer => Scaling::Fit,
+            Scaling::Fit => Scaling::Stretch,
+            Scaling::Stretch => Scaling::Integer,
+        }
+    }
+
+    /// Size of the pixel buffer drawn to a window of `surface` size.
+    /// `pixels` scales the buffer by whole multiples itself, the other modes
+    /// are drawn at the size of the window.
+    pub fn buffer_size(self, display: (u32, u32), surface: (u32, u32)) -> (u32, u32) {
+        match self {
+            Scaling::Integer => display,
+            _ => (surface.0.max(1), surface.1.max(1)),
+        }
+    }
+}
+
+/// Draw the display `vmem` into an RGBA `frame` of `size`.
+pub fn draw(
+    vmem: &Framebuffer,
+    frame: &mut [u8],
+    size: (u32, u32),
+    scaling: Scaling,
+    palette: &Palette,
+) {
+    let (width, height) = (vmem.width(), vmem.height());
+    let (fw, fh) = (size.0 as usize, size.1 as usize);
+    // area of the frame covered by the display
+    let (dw, dh) = match scaling {
+        Scaling::Stretch => (fw, fh),
+        _ => {
+            let scale = (fw as f64 / width as f64).min(fh as f64 / height as f64);
+            (
+                ((width as f64 * scale) as usize).max(1),
+                ((height as f64 * scale) as usize).max(1),
+            )
+        }
+    };
+    let (left, top) = ((fw - dw.min(fw)) / 2, (fh - dh.min(fh)) / 2);
+    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % fw, i / fw);
+        let colour = if x < left || y < top || x >= left + dw || y >= top + dh {
+            Palette::MONO.off
+        } else {
+            let (sx, sy) = ((x - left) * width / dw, (y - top) * height / dh);
+            palette.colour(vmem.get(sx, sy))
+        };
+        pixel.copy_from_slice(&colour);
+    }
+}
+
+/// Window position and size in physical pixels, saved between runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl Geometry {
+    fn path() -> Option<PathBuf> {
+        Some(config::dir()?.join("window.json"))
+    }
+
+    /// The geometry saved by the last run, if any.
+    pub fn load() -> Option<Geometry> {
+        let json = fs::read_to_string(Geometry::path()?).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Geometry::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string(self).map_err(io::Error::from)?;
+        fs::write(path, json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(scaling: Scaling, size: (u32, u32)) -> Vec<String> {
+        // 2x1 display with the left pixel lit
+        let mut vmem = Framebuffer::new(2, 1);
+        vmem.set(0, 0, true);
+        let mut frame = vec![0; (size.0 * size.1 * 4) as usize];
+        draw(&vmem, &mut frame, size, scaling, &Palette::LCD);
+        frame
+            .chunks_exact(4 * size.0 as usize)
+            .map(|row| {
+                row.chunks_exact(4)
+                    .map(|p| match p {
+                        p if p == Palette::LCD.on => '#',
+                        p if p == Palette::LCD.off => '.',
+                        _ => ' ',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn draw_scales_the_display_to_the_frame() {
+        assert_eq!(render(Scaling::Integer, (2, 1)), ["#."]);
+        assert_eq!(
+            render(Scaling::Fit, (5, 4)),
+            ["     ", "###..", "###..", "     "]
+        );
+        assert_eq!(render(Scaling::Stretch, (5, 2)), ["###..", "###.."]);
+    }
+
+    #[test]
+    fn scaling_parses_and_cycles() {
+        assert_eq!(Scaling::parse("fit"), Ok(Scaling::Fit));
+        assert!(Scaling::parse("zoom").is_err());
+        assert_eq!(Scaling::Stretch.next(), Scaling::Integer);
+        assert_eq!(Scaling::Fit.buffer_size((64, 32), (640, 0)), (640, 1));
+        assert_eq!(Scaling::Integer.buffer_size((64, 32), (640, 480)), (64, 32));
+    }
+}
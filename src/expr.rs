@@ -0,0 +1,346 @@
+//! Expressions over the emulator state, used for breakpoint conditions.
+//!
+//! ```text
+//! v3 == 5 && i >= 0x300 && dt == 0
+//! mem[0x3F0] != 0 || mem[i + v0] > 3
+//! ```
+//!
+//! Operands are numbers (decimal or `0x` hexadecimal), the registers `v0` to
+//! `vf`, `i`, `pc`, `dt`, `st` and `sp` (stack depth) and `mem[ADDR]` for a byte
+//! of memory. Operators follow C precedence: `!` and unary `-`, then `* / %`,
+//! `+ -`, `< <= > >=`, `== !=`, `&`, `^`, `|`, `&&` and `||`. Comparisons and
+//! logical operators produce 0 or 1 and any non-zero value is true. Division
+//! by zero produces 0.
+
+use crate::Emulator;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+    Dt,
+    St,
+    Sp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinOp {
+    /// Binding power, higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::BitOr => 3,
+            BinOp::BitXor => 4,
+            BinOp::BitAnd => 5,
+            BinOp::Eq | BinOp::Ne => 6,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 7,
+            BinOp::Add | BinOp::Sub => 8,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 9,
+        }
+    }
+
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            BinOp::Or => (a != 0 || b != 0) as i64,
+            BinOp::And => (a != 0 && b != 0) as i64,
+            BinOp::BitOr => a | b,
+            BinOp::BitXor => a ^ b,
+            BinOp::BitAnd => a & b,
+            BinOp::Eq => (a == b) as i64,
+            BinOp::Ne => (a != b) as i64,
+            BinOp::Lt => (a < b) as i64,
+            BinOp::Le => (a <= b) as i64,
+            BinOp::Gt => (a > b) as i64,
+            BinOp::Ge => (a >= b) as i64,
+            BinOp::Add => a.wrapping_add(b),
+            BinOp::Sub => a.wrapping_sub(b),
+            BinOp::Mul => a.wrapping_mul(b),
+            BinOp::Div => a.checked_div(b).unwrap_or(0),
+            BinOp::Rem => a.checked_rem(b).unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 22] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "(",
+    ")", "[", "]", "=",
+];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<Chars> = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let mut word = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+            {
+                word.push(c);
+                chars.next();
+            }
+            let word = word.to_lowercase();
+            if c.is_ascii_digit() {
+                let n = match word.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                tokens.push(Token::Number(
+                    n.map_err(|_| format!("invalid number '{}'", word))?,
+                ));
+            } else {
+                tokens.push(Token::Ident(word));
+            }
+        } else {
+            let rest: String = chars.clone().take(2).collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("unexpected '{}'", c))?;
+            if *op == "=" {
+                return Err("use '==' to compare".to_string());
+            }
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+fn binary_op(op: &str) -> Option<BinOp> {
+    Some(match op {
+        "||" => BinOp::Or,
+        "&&" => BinOp::And,
+        "|" => BinOp::BitOr,
+        "^" => BinOp::BitXor,
+        "&" => BinOp::BitAnd,
+        "==" => BinOp::Eq,
+        "!=" => BinOp::Ne,
+        "<" => BinOp::Lt,
+        "<=" => BinOp::Le,
+        ">" => BinOp::Gt,
+        ">=" => BinOp::Ge,
+        "+" => BinOp::Add,
+        "-" => BinOp::Sub,
+        "*" => BinOp::Mul,
+        "/" => BinOp::Div,
+        "%" => BinOp::Rem,
+        _ => return None,
+    })
+}
+
+fn register(name: &str) -> Option<Register> {
+    Some(match name {
+        "i" => Register::I,
+        "pc" => Register::Pc,
+        "dt" => Register::Dt,
+        "st" => Register::St,
+        "sp" => Register::Sp,
+        _ => {
+            let n = name.strip_prefix('v')?;
+            if n.len() != 1 {
+                return None;
+            }
+            Register::V(usize::from_str_radix(n, 16).ok()?)
+        }
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(o)) if o == op => Ok(()),
+            _ => Err(format!("expected '{}'", op)),
+        }
+    }
+
+    /// Parse an expression whose operators bind tighter than `min`.
+    fn expr(&mut self, min: u8) -> Result<Expr, String> {
+        let mut lhs = self.operand()?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            let op = match binary_op(op) {
+                Some(op) if op.precedence() > min => op,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.expr(op.precedence())?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) if name == "mem" => {
+                self.expect("[")?;
+                let addr = self.expr(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(addr)))
+            }
+            Some(Token::Ident(name)) => register(&name)
+                .map(Expr::Register)
+                .ok_or_else(|| format!("unknown register '{}'", name)),
+            Some(Token::Op("(")) => {
+                let e = self.expr(0)?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.operand()?))),
+            Some(Token::Op("-")) => Ok(Expr::Neg(Box::new(self.operand()?))),
+            Some(Token::Op(op)) => Err(format!("unexpected '{}'", op)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let e = parser.expr(0)?;
+        match parser.next() {
+            None => Ok(e),
+            Some(Token::Number(n)) => Err(format!("unexpected '{}'", n)),
+            Some(Token::Ident(s)) => Err(format!("unexpected '{}'", s)),
+            Some(Token::Op(op)) => Err(format!("unexpected '{}'", op)),
+        }
+    }
+
+    pub fn eval(&self, e: &Emulator) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Register(r) => match r {
+                Register::V(n) => e.v[*n] as i64,
+                Register::I => e.i as i64,
+                Register::Pc => e.pc as i64,
+                Register::Dt => e.dt as i64,
+                Register::St => e.st as i64,
+                Register::Sp => e.stack_depth() as i64,
+            },
+            Expr::Memory(addr) => {
+                let addr = addr.eval(e).rem_euclid(e.mem.len() as i64);
+                e.peek(addr as usize) as i64
+            }
+            Expr::Not(a) => (a.eval(e) == 0) as i64,
+            Expr::Neg(a) => a.eval(e).wrapping_neg(),
+            Expr::Binary(op, a, b) => op.apply(a.eval(e), b.eval(e)),
+        }
+    }
+
+    pub fn is_true(&self, e: &Emulator) -> bool {
+        self.eval(e) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(s: &str, e: &Emulator) -> i64 {
+        Expr::parse(s).unwrap().eval(e)
+    }
+
+    #[test]
+    fn expr_follows_precedence() {
+        let e = Emulator::new();
+        assert_eq!(eval("1 + 2 * 3", &e), 7);
+        assert_eq!(eval("(1 + 2) * 3", &e), 9);
+        assert_eq!(eval("10 - 4 - 3", &e), 3);
+        assert_eq!(eval("1 | 2 == 2", &e), 1);
+        assert_eq!(eval("0 || 1 && 0", &e), 0);
+        assert_eq!(eval("!0 && -2 < 0", &e), 1);
+        assert_eq!(eval("7 / 0 + 0x10 % 3", &e), 1);
+    }
+
+    #[test]
+    fn expr_reads_registers_and_memory() {
+        let mut e = Emulator::new();
+        e.v[3] = 5;
+        e.v[0xa] = 2;
+        e.i = 0x300;
+        e.mem[0x3f0] = 7;
+        e.mem[0x302] = 1;
+        assert!(Expr::parse("v3 == 5 && i >= 0x300 && dt == 0")
+            .unwrap()
+            .is_true(&e));
+        assert!(Expr::parse("mem[0x3F0] != 0").unwrap().is_true(&e));
+        assert_eq!(eval("mem[i + vA]", &e), 1);
+        assert_eq!(eval("pc + sp", &e), 0x200);
+        assert_eq!(eval("mem[-1]", &e), 0);
+    }
+
+    #[test]
+    fn expr_reports_errors() {
+        assert_eq!(
+            Expr::parse("v3 = 5"),
+            Err("use '==' to compare".to_string())
+        );
+        assert_eq!(Expr::parse("vg"), Err("unknown register 'vg'".to_string()));
+        assert_eq!(Expr::parse("mem[1"), Err("expected ']'".to_string()));
+        assert_eq!(
+            Expr::parse("1 +"),
+            Err("unexpected end of expression".to_string())
+        );
+        assert_eq!(Expr::parse("1 2"), Err("unexpected '2'".to_string()));
+        assert_eq!(Expr::parse("0xz"), Err("invalid number '0xz'".to_string()));
+        assert_eq!(Expr::parse("v1 $ 2"), Err("unexpected '$'".to_string()));
+    }
+}
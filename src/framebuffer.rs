@@ -0,0 +1,202 @@
+//! The display as packed rows of bits, so a sprite row is drawn and checked
+//! for collisions with a few shifts and XORs instead of pixel by pixel.
+//!
+//! A row is a `u128` with the leftmost pixel in bit 0, enough for the 128
+//! pixel wide hi-res modes. Screenshots, recordings and the window still take
+//! one byte per pixel, from `to_pixels`.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    /// Bits of the row that are on the screen.
+    mask: u128,
+    rows: Vec<u128>,
+    /// Changed since it was last shown.
+    dirty: bool,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && width <= 128, "display width {}", width);
+        Framebuffer {
+            width,
+            height,
+            mask: u128::MAX >> (128 - width),
+            rows: vec![0; height],
+            dirty: true,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of pixels.
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.fill(0);
+        self.dirty = true;
+    }
+
+    /// Whether the display changed since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.rows[y] >> x & 1 == 1
+    }
+
+    #[cfg(test)]
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        if on {
+            self.rows[y] |= 1 << x;
+        } else {
+            self.rows[y] &= !(1 << x);
+        }
+    }
+
+    /// XOR the 8 pixels of `bits`, leftmost in the most significant bit, onto
+    /// row `y` starting at `x`. Pixels past the right edge wrap around to the
+    /// left with `wrap` and are clipped otherwise. Returns whether any pixel
+    /// was turned off.
+    pub fn draw_row(&mut self, x: usize, y: usize, bits: u8, wrap: bool) -> bool {
+        let sprite = bits.reverse_bits() as u128;
+        let mut placed = sprite << x & self.mask;
+        if wrap {
+            placed |= sprite.checked_shr((self.width - x) as u32).unwrap_or(0);
+        }
+        let row = &mut self.rows[y];
+        let collided = *row & placed != 0;
+        *row ^= placed;
+        self.dirty |= placed != 0;
+        collided
+    }
+
+    /// The byte at `offset` when the display is 8 pixels per byte, row by
+    /// row, leftmost pixel in the most significant bit.
+    pub fn byte(&self, offset: usize) -> u8 {
+        let (y, x) = (offset / (self.width / 8), offset % (self.width / 8) * 8);
+        ((self.rows[y] >> x) as u8).reverse_bits()
+    }
+
+    pub fn set_byte(&mut self, offset: usize, value: u8) {
+        let (y, x) = (offset / (self.width / 8), offset % (self.width / 8) * 8);
+        self.rows[y] &= !(0xff << x);
+        self.rows[y] |= (value.reverse_bits() as u128) << x;
+        self.dirty = true;
+    }
+
+    /// One byte per pixel, 1 for on, row by row.
+    pub fn to_pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.len());
+        for row in &self.rows {
+            pixels.extend((0..self.width).map(|x| (row >> x & 1) as u8));
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// The display as one byte per pixel, drawn to pixel by pixel.
+    fn draw_pixels(
+        pixels: &mut [u8],
+        width: usize,
+        x: usize,
+        y: usize,
+        bits: u8,
+        wrap: bool,
+    ) -> bool {
+        let mut collided = false;
+        for col in 0..8 {
+            let mut px = x + col;
+            if px >= width {
+                if !wrap {
+                    break;
+                }
+                px -= width;
+            }
+            if bits >> (7 - col) & 1 == 1 {
+                let idx = px + y * width;
+                collided |= pixels[idx] == 1;
+                pixels[idx] ^= 1;
+            }
+        }
+        collided
+    }
+
+    #[test]
+    fn rows_draw_like_pixels() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for (width, height) in [(64, 32), (128, 64)] {
+            for wrap in [false, true] {
+                let mut fb = Framebuffer::new(width, height);
+                let mut pixels = vec![0; width * height];
+                for _ in 0..2000 {
+                    let (x, y) = (rng.gen_range(0..width), rng.gen_range(0..height));
+                    let bits = rng.gen();
+                    let collided = draw_pixels(&mut pixels, width, x, y, bits, wrap);
+                    assert_eq!(fb.draw_row(x, y, bits, wrap), collided);
+                }
+                assert_eq!(fb.to_pixels(), pixels);
+            }
+        }
+    }
+
+    #[test]
+    fn rows_wrap_or_clip_at_the_right_edge() {
+        let mut fb = Framebuffer::new(64, 32);
+        fb.draw_row(60, 1, 0xff, false);
+        assert_eq!(fb.rows[1], 0xf << 60);
+        fb.clear();
+        fb.draw_row(60, 1, 0xff, true);
+        assert_eq!(fb.rows[1], 0xf << 60 | 0xf);
+        assert!(fb.get(0, 1) && fb.get(63, 1) && !fb.get(4, 1));
+
+        let mut fb = Framebuffer::new(128, 64);
+        assert!(!fb.draw_row(124, 0, 0xff, true));
+        assert!(fb.draw_row(0, 0, 0x80, true));
+        assert!(!fb.get(0, 0) && fb.get(127, 0) && fb.get(3, 0));
+    }
+
+    #[test]
+    fn bytes_are_8_pixels() {
+        let mut fb = Framebuffer::new(64, 32);
+        fb.set_byte(9, 0b1010_0001);
+        assert_eq!(fb.to_pixels()[72..80], [1, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(fb.byte(9), 0b1010_0001);
+        assert_eq!(fb.byte(8), 0);
+        fb.set(15, 1, false);
+        fb.set(0, 0, true);
+        assert_eq!(fb.byte(9), 0b1010_0000);
+        assert_eq!(fb.byte(0), 0b1000_0000);
+    }
+
+    #[test]
+    fn changes_make_it_dirty() {
+        let mut fb = Framebuffer::new(64, 32);
+        assert!(fb.take_dirty());
+        assert!(!fb.take_dirty());
+        fb.draw_row(0, 0, 0, true);
+        assert!(!fb.take_dirty());
+        fb.draw_row(0, 0, 0x80, true);
+        assert!(fb.take_dirty());
+        fb.clear();
+        assert!(fb.take_dirty());
+        fb.set_byte(0, 1);
+        assert!(fb.take_dirty());
+    }
+}
@@ -0,0 +1,53 @@
+use crate::audio::WavRecorder;
+use crate::debugger;
+use crate::{Emulator, Idle};
+use std::time::Instant;
+
+/// Run the emulator without a window for a fixed number of frames, or until
+/// the program halts by jumping to itself. When the debugger stops execution,
+/// commands are read from stdin until it resumes.
+pub fn run(emulator: &mut Emulator, frames: u32, mut wav: Option<&mut WavRecorder>) {
+    for frame in 1..=frames {
+        emulator.run_frame();
+        let mut quit = false;
+        if emulator.debug.is_stopped() {
+            println!("{}", debugger::report(emulator));
+            quit = !debugger::prompt(emulator);
+        }
+        // the frame that ran is recorded even when it is the last one
+        if let Some(recorder) = wav.as_mut() {
+            if let Err(e) = recorder.push_frame(&emulator.sound()) {
+                println!("WAV recording failed: {}", e);
+                break;
+            }
+        }
+        if quit {
+            break;
+        }
+        if emulator.idle() == Some(Idle::Halted) {
+            println!("Halted at 0x{:03X} after {} frames", emulator.pc, frame);
+            break;
+        }
+    }
+}
+
+/// Run `frames` frames as fast as possible and report the speed of the
+/// interpreter.
+pub fn benchmark(emulator: &mut Emulator, frames: u32) -> String {
+    let cycles = emulator.cycles;
+    let start = Instant::now();
+    for _ in 0..frames {
+        emulator.run_frame();
+        if emulator.debug.is_stopped() {
+            break;
+        }
+    }
+    let secs = start.elapsed().as_secs_f64();
+    let instructions = emulator.cycles - cycles;
+    format!(
+        "Ran {} instructions in {:.3} s, {:.2} million instructions per second",
+        instructions,
+        secs,
+        instructions as f64 / secs.max(f64::EPSILON) / 1e6
+    )
+}
@@ -1,20 +1,135 @@
+#[cfg(test)]
+mod asm;
+mod audio;
+mod capture;
+mod cdp1802;
+mod cheat;
+mod clock;
+mod config;
+#[cfg(test)]
+mod conformance;
+mod coverage;
+mod debugger;
+mod decode;
+mod disasm;
+mod display;
+mod expr;
+mod framebuffer;
+mod headless;
+mod memory;
+mod options;
+mod palette;
+mod profile;
+mod quirks;
+mod romdb;
+mod timing;
+mod trace;
+mod tracediff;
+mod vip;
+
+use audio::{Sound, WavRecorder};
+use capture::GifRecorder;
+use cdp1802::Cdp1802;
+use cheat::Cheats;
+use clock::{Clock, FrameRate};
+use coverage::Coverage;
+use debugger::{Access, Debugger};
+use decode::{DecodeCache, Op};
+use display::{Geometry, Scaling};
+use framebuffer::Framebuffer;
 use log::error;
+use memory::Memory;
+use options::Options;
+use palette::Palette;
 use pixels::{Pixels, SurfaceTexture};
+use profile::Profiler;
+use quirks::Quirks;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use romdb::{Database, RomInfo};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
-use std::time::{Duration, SystemTime};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use std::{env, process};
-use winit::dpi::LogicalSize;
-use winit::event::{Event, VirtualKeyCode};
+use timing::Timing;
+use trace::Tracer;
+use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
+use winit::event::{Event, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::WindowBuilder;
+use winit::window::{Fullscreen, WindowBuilder};
 use winit_input_helper::WinitInputHelper;
 
 const WIDTH: u32 = 64;
 const HEIGHT: u32 = 32;
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+const DEFAULT_IPF: u32 = 12;
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("trace-diff") {
+        trace_diff(&args[1..]);
+    }
+    let options = Options::parse(args).unwrap_or_else(|e| {
+        println!("{}\n\n{}", e, options::USAGE);
+        process::exit(1);
+    });
+    let database = (!options.no_database).then(Database::load);
+    let rom = read_rom(&options.rom, options.memory_size).unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(1);
+    });
+    let (mut emulator, info) = start(&rom, &options, database.as_ref());
+    let mut palette = options.palette.or(info.palette).unwrap_or_default();
+    emulator.debug.breakpoints = options.breakpoints.clone();
+    emulator.debug.watchpoints = options.watchpoints.clone();
+
+    if let Some(path) = &options.trace {
+        let tracer = Tracer::create(
+            path,
+            options.trace_filter.clone(),
+            options.trace_after,
+            options.trace_limit,
+        )
+        .unwrap_or_else(|e| {
+            println!("Failed to create {}: {}", path.display(), e);
+            process::exit(1);
+        });
+        emulator.tracer = Some(tracer);
+    }
+    if options.profile.is_some() || options.profile_folded.is_some() {
+        emulator.profiler = Some(Profiler::default());
+    }
+    if options.coverage.is_some() || options.coverage_image.is_some() {
+        emulator.coverage = Some(Coverage::new(emulator.mem.len(), 0x200, rom.len()));
+    }
+
+    let mut wav = options.wav.as_ref().map(|path| {
+        WavRecorder::create(path).unwrap_or_else(|e| {
+            println!("Failed to create {}: {}", path.display(), e);
+            process::exit(1);
+        })
+    });
+
+    if options.headless {
+        if options.benchmark {
+            println!("{}", headless::benchmark(&mut emulator, options.frames));
+        } else {
+            headless::run(&mut emulator, options.frames, wav.as_mut());
+        }
+        if let Some(recorder) = wav {
+            stop_wav(recorder);
+        }
+        stop_trace(&mut emulator);
+        stop_profile(&mut emulator, &options);
+        stop_coverage(&mut emulator, &options);
+        return;
+    }
+
     let key_map: HashMap<VirtualKeyCode, u8> = HashMap::from([
         (VirtualKeyCode::Key1, 0x0),
         (VirtualKeyCode::Key2, 0x1),
@@ -35,63 +150,109 @@ fn main() {
     ]);
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
+    // --scale takes precedence over the geometry of the last run
+    let saved = Geometry::load().filter(|_| options.scale.is_none());
     let window = {
-        let size = LogicalSize::new((WIDTH * 10) as f64, (HEIGHT * 10) as f64);
-        WindowBuilder::new()
-            .with_title("CHIP8")
-            .with_inner_size(size)
-            .with_min_inner_size(size)
+        let scale = options.scale.unwrap_or(10);
+        let mut builder = WindowBuilder::new()
+            .with_title(window_title(&info))
+            .with_inner_size(LogicalSize::new(WIDTH * scale, HEIGHT * scale))
+            .with_min_inner_size(LogicalSize::new(WIDTH, HEIGHT));
+        if let Some(g) = saved {
+            builder = builder
+                .with_inner_size(PhysicalSize::new(g.width, g.height))
+                .with_position(PhysicalPosition::new(g.x, g.y));
+        }
+        builder
             .build(&event_loop)
             .expect("Failed to initialize window")
     };
+    if options.fullscreen || saved.is_some_and(|g| g.fullscreen) {
+        window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+    let mut scaling = options.scaling;
+    let mut surface_size = window.inner_size();
+    let mut buffer_size =
+        scaling.buffer_size((WIDTH, HEIGHT), (surface_size.width, surface_size.height));
     let mut pixels = {
-        let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH, HEIGHT, surface_texture).expect("Failed to initialize pixels display")
+        let surface_texture = SurfaceTexture::new(surface_size.width, surface_size.height, &window);
+        Pixels::new(buffer_size.0, buffer_size.1, surface_texture)
+            .expect("Failed to initialize pixels display")
     };
-    let mut emulator = Emulator::new();
-
-    // Load a rom
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("No path to rom provided.");
-        process::exit(1);
+    // Size and position of the window when not fullscreen, saved on exit
+    let mut geometry = Geometry {
+        width: surface_size.width,
+        height: surface_size.height,
+        ..saved.unwrap_or_default()
+    };
+    if let Ok(pos) = window.outer_position() {
+        geometry.x = pos.x;
+        geometry.y = pos.y;
     }
-    let mut f = File::open(&args[1]).unwrap_or_else(|e| {
-        println!("{}", e);
-        process::exit(1);
-    });
-    let mut rom = Vec::new();
-    f.read_to_end(&mut rom).unwrap_or_else(|e| {
-        println!("{}", e);
-        process::exit(1);
+
+    // Debugger commands are read on a separate thread so stdin doesn't block
+    // the event loop
+    let commands = options.debug.then(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                if line.ok().and_then(|line| tx.send(line).ok()).is_none() {
+                    break;
+                }
+            }
+        });
+        rx
     });
-    emulator.load_rom(&rom);
 
+    let mut rom = rom;
+    let mut rom_path = options.rom.clone();
+    let mut gif: Option<GifRecorder> = None;
+    let mut clock = Clock::new(options.fast_forward, options.slow_motion);
+    let mut title = window_title(&info);
+    let mut shown_title = title.clone();
     let mut t = SystemTime::now();
-    let mut dt: Duration = Duration::new(0, 0);
+    // The window needs drawing even if the display didn't change
+    let mut redraw = true;
+    let mut next_frame = Instant::now();
+    // Sleeping until an event instead of the next frame
+    let mut asleep = false;
+    let mut frame_rate = FrameRate::new(next_frame);
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
-        // Draw
-        if Event::MainEventsCleared == event {
-            emulator.draw(pixels.get_frame());
-            if pixels
-                .render()
-                .map_err(|e| error!("pixels.render() failed: {}", e))
-                .is_err()
-            {
-                *control_flow = ControlFlow::Exit;
-                return;
+        if let Event::RedrawRequested(_) = event {
+            redraw = true;
+        }
+
+        // Load a ROM dropped on the window, and keep track of where it is
+        let mut load = None;
+        if let Event::WindowEvent { event, .. } = &event {
+            match event {
+                WindowEvent::DroppedFile(path) => load = Some(path.clone()),
+                WindowEvent::Moved(pos) if window.fullscreen().is_none() => {
+                    geometry.x = pos.x;
+                    geometry.y = pos.y;
+                }
+                _ => {}
+            }
+        }
+
+        // Debugger commands
+        let mut quit = false;
+        for line in commands.iter().flat_map(|rx| rx.try_iter()) {
+            if debugger::is_quit(&line) {
+                quit = true;
+            } else {
+                let out = debugger::execute(&mut emulator, &line);
+                if !out.is_empty() {
+                    println!("{}", out);
+                }
             }
         }
 
         // Handle input
+        let mut reset = false;
         if input.update(&event) {
             // Close events
-            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
-                *control_flow = ControlFlow::Exit;
-                return;
-            }
+            quit |= input.key_pressed(VirtualKeyCode::Escape) || input.quit();
 
             for (k, v) in key_map.iter() {
                 if input.key_released(*k) {
@@ -102,24 +263,449 @@ fn main() {
                 }
             }
 
+            // Speed controls
+            if input.key_pressed(VirtualKeyCode::P) {
+                clock.toggle_pause();
+            }
+            if input.key_pressed(VirtualKeyCode::N) {
+                clock.advance();
+            }
+            if input.key_pressed(VirtualKeyCode::M) {
+                clock.slow_motion = !clock.slow_motion;
+            }
+            clock.fast_forward = input.key_held(VirtualKeyCode::Tab);
+
+            // Soft reset and reload the ROM from disk
+            reset |= input.key_pressed(VirtualKeyCode::F5);
+            if input.key_pressed(VirtualKeyCode::F6) {
+                load = Some(rom_path.clone());
+            }
+
+            // Captures
+            if input.key_pressed(VirtualKeyCode::F12) {
+                let path = capture::capture_path(&options.capture_dir, &rom_path, "png");
+                match capture::save_png(
+                    &path,
+                    &emulator.vmem.to_pixels(),
+                    WIDTH as usize,
+                    options.capture_scale as usize,
+                    &palette,
+                ) {
+                    Ok(()) => println!("Saved screenshot to {}", path.display()),
+                    Err(e) => println!("Failed to save screenshot: {}", e),
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::F10) {
+                if let Some(recorder) = gif.take() {
+                    stop_gif(recorder);
+                } else {
+                    let path = capture::capture_path(&options.capture_dir, &rom_path, "gif");
+                    match GifRecorder::create(
+                        &path,
+                        WIDTH as usize,
+                        HEIGHT as usize,
+                        options.capture_scale as usize,
+                        &palette,
+                    ) {
+                        Ok(recorder) => {
+                            println!("Recording GIF to {}", path.display());
+                            gif = Some(recorder);
+                        }
+                        Err(e) => println!("Failed to start GIF recording: {}", e),
+                    }
+                }
+            }
+
+            // Display settings
+            if input.key_pressed(VirtualKeyCode::F11) {
+                if window.fullscreen().is_some() {
+                    window.set_fullscreen(None);
+                } else {
+                    window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                }
+            }
+            let mut resize = input.window_resized();
+            if input.key_pressed(VirtualKeyCode::F9) {
+                scaling = scaling.next();
+                println!("Scaling: {:?}", scaling);
+                resize = resize.or(Some(surface_size));
+            }
+
             // Resize the window
-            if let Some(size) = input.window_resized() {
+            if let Some(size) = resize {
+                redraw = true;
+                surface_size = size;
+                if window.fullscreen().is_none() && size.width > 0 && size.height > 0 {
+                    geometry.width = size.width;
+                    geometry.height = size.height;
+                }
                 pixels.resize_surface(size.width, size.height);
+                let size = scaling.buffer_size((WIDTH, HEIGHT), (size.width, size.height));
+                if size != buffer_size {
+                    pixels.resize_buffer(size.0, size.1);
+                    buffer_size = size;
+                }
             }
         }
 
+        if quit {
+            if let Some(recorder) = gif.take() {
+                stop_gif(recorder);
+            }
+            if let Some(recorder) = wav.take() {
+                stop_wav(recorder);
+            }
+            stop_trace(&mut emulator);
+            stop_profile(&mut emulator, &options);
+            stop_coverage(&mut emulator, &options);
+            geometry.fullscreen = window.fullscreen().is_some();
+            if let Err(e) = geometry.save() {
+                println!("Failed to save the window geometry: {}", e);
+            }
+            *control_flow = ControlFlow::Exit;
+            return;
+        }
+
+        if let Some(path) = load {
+            match read_rom(&path, options.memory_size) {
+                Ok(new_rom) => {
+                    if new_rom != rom {
+                        // coverage is per ROM, start over for a different one
+                        if let Some(coverage) = emulator.coverage.as_mut() {
+                            *coverage = Coverage::new(coverage.len(), 0x200, new_rom.len());
+                        }
+                    }
+                    println!("Loaded {}", path.display());
+                    rom = new_rom;
+                    rom_path = path;
+                    reset = true;
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+        if reset {
+            let info = restart(&mut emulator, &rom, &options, database.as_ref());
+            palette = options.palette.or(info.palette).unwrap_or_default();
+            title = window_title(&info);
+            redraw = true;
+        }
+
         let now = SystemTime::now();
-        dt += now.duration_since(t).expect("clock may have gone backwards!");
-        if dt.as_millis() > 16 {
-            if emulator.dt > 0 {
-                emulator.dt -= 1;
+        let mut elapsed = now
+            .duration_since(t)
+            .expect("clock may have gone backwards!");
+        t = now;
+        if emulator.debug.is_stopped() {
+            elapsed = Duration::new(0, 0);
+        }
+        if asleep {
+            // nothing happened while idle, pick up with the next frame
+            elapsed = elapsed.min(FRAME_TIME);
+            asleep = false;
+        }
+        let frames = clock.frames(elapsed);
+        frame_rate.count(frames, Instant::now());
+        for _ in 0..frames {
+            emulator.run_frame();
+            if let Some(recorder) = gif.as_mut() {
+                if let Err(e) = recorder.push_frame(&emulator.vmem.to_pixels()) {
+                    println!("GIF recording failed: {}", e);
+                    gif = None;
+                }
             }
-            t = now;
+            if let Some(recorder) = wav.as_mut() {
+                if let Err(e) = recorder.push_frame(&emulator.sound()) {
+                    println!("WAV recording failed: {}", e);
+                    wav = None;
+                }
+            }
+            if emulator.debug.is_stopped() {
+                println!("{}", debugger::report(&emulator));
+                break;
+            }
+        }
+
+        let mut status: Vec<String> = clock.status().into_iter().collect();
+        if let Some(fps) = frame_rate.fps().filter(|_| options.show_fps) {
+            status.push(format!("{} fps", fps));
+        }
+        let status = if status.is_empty() {
+            title.clone()
+        } else {
+            format!("{} [{}]", title, status.join(", "))
+        };
+        if status != shown_title {
+            window.set_title(&status);
+            shown_title = status;
+        }
+
+        // Draw once all events are handled, if anything changed, and sleep
+        // until the next frame is due, or until a key is pressed while the
+        // program is idle and the timers have run down
+        if Event::MainEventsCleared == event {
+            if emulator.vmem.take_dirty() || redraw {
+                redraw = false;
+                emulator.draw(pixels.get_frame(), buffer_size, scaling, &palette);
+                if pixels
+                    .render()
+                    .map_err(|e| error!("pixels.render() failed: {}", e))
+                    .is_err()
+                {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+            }
+            // recordings keep running in wall-clock time
+            asleep = commands.is_none()
+                && gif.is_none()
+                && wav.is_none()
+                && (clock.paused
+                    || emulator.idle().is_some() && emulator.dt == 0 && !emulator.sound().active);
+            if asleep {
+                *control_flow = ControlFlow::Wait;
+                return;
+            }
+            let now = Instant::now();
+            if next_frame <= now {
+                next_frame += FRAME_TIME;
+                // don't try to catch up after falling behind, the clock
+                // already runs the frames that are owed
+                if next_frame <= now {
+                    next_frame = now + FRAME_TIME;
+                }
+            }
+            *control_flow = ControlFlow::WaitUntil(next_frame);
         }
-        emulator.process();
     });
 }
 
+/// Programs are loaded after the interpreter area.
+const ROM_START: usize = 0x200;
+
+/// Read a ROM file, checking that it fits in `memory_size` bytes of memory.
+fn read_rom(path: &Path, memory_size: usize) -> Result<Vec<u8>, String> {
+    let rom =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if rom.len() > memory_size - ROM_START {
+        return Err(format!(
+            "{} is too large, {} bytes where at most {} fit in memory",
+            path.display(),
+            rom.len(),
+            memory_size - ROM_START
+        ));
+    }
+    Ok(rom)
+}
+
+/// Create an emulator running `rom`, set up from the options and what the ROM
+/// database knows about it.
+fn start(rom: &[u8], options: &Options, database: Option<&Database>) -> (Emulator, RomInfo) {
+    let info = database.and_then(|db| db.lookup(rom));
+    if let Some(info) = &info {
+        print_rom_info(info);
+    }
+    let info = info.unwrap_or_default();
+
+    let mut emulator = Emulator::with_memory_size(options.memory_size);
+    for range in &options.protect {
+        emulator
+            .mem
+            .protect(*range.start() as usize..*range.end() as usize + 1);
+    }
+    if options.vip {
+        emulator.use_vip_layout();
+    }
+    if options.vip_timing {
+        emulator.timing = Some(Timing::default());
+    }
+    emulator.key_beep = options.key_beep;
+    if let Some(addr) = options.map_display {
+        emulator.mem.map_display(addr as usize, emulator.vmem.len());
+    }
+    if let Some(path) = &options.vip_interpreter {
+        match std::fs::read(path) {
+            Ok(image) if image.len() <= vip::MAX_INTERPRETER_SIZE => {
+                vip::boot(&mut emulator, &image)
+            }
+            Ok(_) => {
+                println!(
+                    "{} is too large for an interpreter, at most {} bytes fit",
+                    path.display(),
+                    vip::MAX_INTERPRETER_SIZE
+                );
+                process::exit(1);
+            }
+            Err(e) => {
+                println!("Failed to read {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(seed) = options.seed {
+        emulator.rng = StdRng::seed_from_u64(seed);
+    }
+    emulator.load_rom(rom);
+    emulator.ipf = options.ipf.or(info.tickrate).unwrap_or(DEFAULT_IPF);
+    emulator.quirks = info.quirks.unwrap_or_default();
+    emulator.quirks.vblank |= options.display_wait;
+    if let Some(wrap) = options.wrap {
+        emulator.quirks.wrap = wrap;
+    }
+    emulator.quirks.collision_rows |= options.collision_rows;
+    if !options.no_cheats {
+        load_cheats(&mut emulator, rom);
+    }
+    (emulator, info)
+}
+
+/// Replace the emulator by a fresh one running `rom`. Breakpoints, watchpoints
+/// and the trace, profile and coverage being recorded carry over.
+fn restart(
+    emulator: &mut Emulator,
+    rom: &[u8],
+    options: &Options,
+    database: Option<&Database>,
+) -> RomInfo {
+    let (mut fresh, info) = start(rom, options, database);
+    fresh.debug.breakpoints = std::mem::take(&mut emulator.debug.breakpoints);
+    fresh.debug.watchpoints = std::mem::take(&mut emulator.debug.watchpoints);
+    fresh.tracer = emulator.tracer.take();
+    fresh.profiler = emulator.profiler.take();
+    fresh.coverage = emulator.coverage.take();
+    *emulator = fresh;
+    info
+}
+
+fn window_title(info: &RomInfo) -> String {
+    match info.title.as_str() {
+        "" => "CHIP8".to_string(),
+        title => format!("CHIP8 - {}", title),
+    }
+}
+
+/// Keys of the keyboard mapped to the CHIP-8 keys 0 to F.
+const KEY_LABELS: [&str; 16] = [
+    "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V",
+];
+
+fn print_rom_info(info: &RomInfo) {
+    match &info.platform {
+        Some(platform) => println!("{} ({})", info.title, platform),
+        None => println!("{}", info.title),
+    }
+    if !info.keys.is_empty() {
+        let keys: Vec<String> = info
+            .keys
+            .iter()
+            .map(|(name, key)| format!("{} = {}", name, KEY_LABELS[*key as usize & 0xf]))
+            .collect();
+        println!("Keys: {}", keys.join(", "));
+    }
+}
+
+/// Load the cheats saved for this ROM, they are saved again when changed.
+fn load_cheats(emulator: &mut Emulator, rom: &[u8]) {
+    let path = match config::dir() {
+        Some(dir) => dir
+            .join("cheats")
+            .join(format!("{}.txt", config::rom_hash(rom))),
+        None => return,
+    };
+    match Cheats::load(&path) {
+        Ok(cheats) => {
+            if !cheats.freezes.is_empty() {
+                println!(
+                    "Loaded {} cheats from {}",
+                    cheats.freezes.len(),
+                    path.display()
+                );
+            }
+            emulator.cheats = cheats;
+        }
+        Err(e) => println!("Failed to load cheats from {}: {}", path.display(), e),
+    }
+}
+
+fn stop_profile(emulator: &mut Emulator, options: &Options) {
+    let profiler = match emulator.profiler.take() {
+        Some(p) => p,
+        None => return,
+    };
+    if let Some(path) = &options.profile {
+        if let Err(e) = std::fs::write(path, profiler.report()) {
+            println!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+    if let Some(path) = &options.profile_folded {
+        if let Err(e) = File::create(path).and_then(|f| profiler.write_folded(BufWriter::new(f))) {
+            println!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn stop_coverage(emulator: &mut Emulator, options: &Options) {
+    let coverage = match emulator.coverage.take() {
+        Some(c) => c,
+        None => return,
+    };
+    if let Some(path) = &options.coverage {
+        if let Err(e) = std::fs::write(path, coverage.report()) {
+            println!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+    if let Some(path) = &options.coverage_image {
+        if let Err(e) = coverage.save_image(path) {
+            println!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn trace_diff(args: &[String]) -> ! {
+    let (files, context) = match args {
+        [a, b] => ([a, b], 5),
+        [a, b, flag, n] if flag == "--context" => match n.parse() {
+            Ok(n) => ([a, b], n),
+            Err(_) => {
+                println!("invalid context '{}'", n);
+                process::exit(2);
+            }
+        },
+        _ => {
+            println!("Usage: chip8-rust trace-diff <TRACE> <TRACE> [--context <LINES>]");
+            process::exit(2);
+        }
+    };
+    match tracediff::run(Path::new(files[0]), Path::new(files[1]), context) {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            println!("{}", e);
+            process::exit(2);
+        }
+    }
+}
+
+fn stop_gif(recorder: GifRecorder) {
+    match recorder.finish() {
+        Ok(()) => println!("GIF recording stopped"),
+        Err(e) => println!("Failed to finish GIF recording: {}", e),
+    }
+}
+
+fn stop_wav(recorder: WavRecorder) {
+    if let Err(e) = recorder.finish() {
+        println!("Failed to finish WAV recording: {}", e);
+    }
+}
+
+fn stop_trace(emulator: &mut Emulator) {
+    if let Some(mut tracer) = emulator.tracer.take() {
+        if let Err(e) = tracer.flush() {
+            println!("Failed to write trace: {}", e);
+        }
+    }
+}
+
 const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -139,233 +725,631 @@ const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// Why a program can't make progress until a key is pressed, if ever.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Idle {
+    /// `1NNN` jumping to itself, how programs end.
+    Halted,
+    /// `FX0A` waiting for a key.
+    WaitingForKey,
+}
+
 struct Emulator {
     i: u16,
     pc: u16,
-    mem: [u8; 4096],
+    mem: Memory,
     v: [u8; 16],
     stack: Vec<u16>,
-    vmem: [u8; 32 * 64],
+    /// Memory is laid out like the COSMAC VIP, where `0NNN` calls machine code.
+    vip: bool,
+    /// Top of the stack when it is kept in memory instead of `stack`.
+    stack_pointer: Option<u16>,
+    vmem: Framebuffer,
     keypad: [bool; 16],
+    /// Keys released since `FX0A` started waiting, one bit per key.
+    released: u16,
+    /// Register `FX0A` stores the key in once one is pressed and released.
+    waiting_for_key: Option<usize>,
+    /// Beep while a key is held during `FX0A`, as the VIP does.
+    key_beep: bool,
     dt: u8,
+    st: u8,
+    // XO-CHIP audio pattern and pitch, the buzzer is used until a pattern is loaded
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+    // instructions executed per 60 Hz frame
+    ipf: u32,
+    rng: StdRng,
+    // number of instructions executed so far
+    cycles: u64,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    debug: Debugger,
+    cheats: Cheats,
+    quirks: Quirks,
+    /// Instructions already decoded, by address.
+    decoded: DecodeCache,
+    /// Cycles left in the frame when running with VIP timing instead of
+    /// `ipf` instructions per frame.
+    timing: Option<Timing>,
+    /// The CPU of the VIP, for `0NNN` or running the interpreter.
+    cpu: Cdp1802,
+    /// Key tested by the CPU, selected with `OUT 2`.
+    key_latch: u8,
+    /// Run the original interpreter on the CPU instead of emulating CHIP-8.
+    interpreter: bool,
+    /// A sprite was drawn with the vblank quirk, nothing more runs until the
+    /// next frame.
+    wait_for_vblank: bool,
 }
 
 impl Emulator {
+    #[cfg(test)]
     fn new() -> Self {
+        Self::with_memory_size(4096)
+    }
+
+    fn with_memory_size(size: usize) -> Self {
         let mut e = Self {
             i: 0,
             pc: 0x200,
-            mem: [0x0; 4096],
+            mem: Memory::new(size),
             v: [0x0; 16],
             stack: Vec::new(),
-            vmem: [0x0; 32 * 64],
+            vip: false,
+            stack_pointer: None,
+            vmem: Framebuffer::new(WIDTH as usize, HEIGHT as usize),
             keypad: [false; 16],
+            released: 0,
+            waiting_for_key: None,
+            key_beep: false,
             dt: 0,
+            st: 0,
+            pattern: None,
+            pitch: 64,
+            ipf: DEFAULT_IPF,
+            rng: StdRng::from_entropy(),
+            cycles: 0,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            debug: Debugger::default(),
+            cheats: Cheats::default(),
+            quirks: Quirks::default(),
+            decoded: DecodeCache::new(size),
+            timing: None,
+            cpu: Cdp1802::default(),
+            key_latch: 0,
+            interpreter: false,
+            wait_for_vblank: false,
         };
         // Load Font into memory at 0x50 - 0x9f
         e.mem[0x50..=0x9f].copy_from_slice(&FONT);
-        return e;
+        e
     }
 
     fn load_rom(&mut self, rom: &[u8]) {
-        if rom.len() > (4096 - 512) {
+        if rom.len() > self.mem.len() - ROM_START {
             panic!("size too large to load into memory")
         }
 
         for (i, v) in rom.iter().enumerate() {
-            self.mem[0x200..][i] = *v;
+            self.mem[ROM_START..][i] = *v;
         }
     }
 
-    fn draw(&self, frame: &mut [u8]) {
-        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            if self.vmem[i] == 1 {
-                pixel.copy_from_slice(&[0xff, 0xff, 0xff, 0xff])
-            } else {
-                pixel.copy_from_slice(&[0x0, 0x0, 0x0, 0xff])
-            }
-        }
+    fn draw(&self, frame: &mut [u8], size: (u32, u32), scaling: Scaling, palette: &Palette) {
+        display::draw(&self.vmem, frame, size, scaling, palette);
     }
 
     fn set_key_state(&mut self, key: u8, state: bool) {
+        if self.keypad[key as usize] && !state {
+            self.released |= 1 << key;
+        }
         self.keypad[key as usize] = state;
     }
 
+    fn sound(&self) -> Sound {
+        Sound {
+            active: self.st > 0
+                || self.key_beep && self.waiting_for_key.is_some() && self.keypad.contains(&true),
+            pattern: self.pattern,
+            pitch: self.pitch,
+        }
+    }
+
+    /// Run one 60 Hz frame: tick the timers, execute `ipf` instructions and
+    /// reapply frozen cheat values.
+    /// The frame ends early when the debugger stops execution.
+    fn run_frame(&mut self) {
+        if self.debug.is_stopped() {
+            return;
+        }
+        if self.interpreter {
+            vip::run_frame(self);
+            cheat::apply(self);
+            return;
+        }
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+        match self.timing.as_mut() {
+            Some(timing) => {
+                timing.begin_frame();
+                while self.timing.as_ref().is_some_and(Timing::has_cycles) {
+                    self.step();
+                    if self.debug.is_stopped() {
+                        break;
+                    }
+                }
+            }
+            None => {
+                self.wait_for_vblank = false;
+                for _ in 0..self.ipf {
+                    self.step();
+                    if self.debug.is_stopped() || self.wait_for_vblank {
+                        break;
+                    }
+                }
+            }
+        }
+        cheat::apply(self);
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
+        }
+    }
+
+    /// Whether the program is idle, only the timers run. Unknown when running
+    /// the original interpreter.
+    fn idle(&self) -> Option<Idle> {
+        if self.interpreter {
+            None
+        } else if self.waiting_for_key.is_some() {
+            Some(Idle::WaitingForKey)
+        } else if decode::decode(Emulator::read_word(&self.mem, self.pc)) == Op::Jump(self.pc) {
+            Some(Idle::Halted)
+        } else {
+            None
+        }
+    }
+
+    /// Execute the next instruction unless a breakpoint or watchpoint stops
+    /// execution before it.
+    fn step(&mut self) {
+        if self.debug.is_stopped() {
+            return;
+        }
+        if !self.debug.is_armed() || !self.with_debugger(|d, e| d.should_break(e)) {
+            self.process();
+        }
+    }
+
+    /// Give the debugger access to the emulator so conditions can be evaluated.
+    fn with_debugger<T>(&mut self, f: impl FnOnce(&mut Debugger, &Emulator) -> T) -> T {
+        let mut debug = std::mem::take(&mut self.debug);
+        let result = f(&mut debug, self);
+        self.debug = debug;
+        result
+    }
+
     fn process(&mut self) {
         // Fetch instruction from memory and move PC forward
-        let instr = Emulator::read_word(self.mem, self.pc);
-        self.pc += 2;
+        let pc = self.pc;
+        let instr = (self.peek(pc as usize) as u16) << 8 | self.peek(pc as usize + 1) as u16;
+        // watchpoints on execution are checked before the fetch by the debugger
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(pc as usize, 2, coverage::EXECUTED);
+        }
+        self.debug.begin(pc, instr);
+        self.pc = self.pc.wrapping_add(2);
+
+        self.trace(pc, instr, false);
+        let op = self.decoded.get(pc, instr);
+        let cost = self.timing.is_some().then(|| timing::cost(self, op));
+        self.execute(op);
+        if let (Some(timing), Some(cost)) = (self.timing.as_mut(), cost) {
+            timing.charge(op, cost);
+        }
+        self.trace(pc, instr, true);
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(pc, instr, self.pc);
+        }
+        self.cycles += 1;
+    }
+
+    /// XOR the `n` byte sprite at I onto the display at (`x`, `y`), returning
+    /// what `DXYN` sets VF to: 1 if any pixel was turned off, or with the
+    /// `collision_rows` quirk the number of rows that collided or were clipped
+    /// at the bottom.
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) -> u8 {
+        // the position wraps, only the sprite itself is clipped
+        let (width, height) = (self.vmem.width(), self.vmem.height());
+        let x = x as usize % width;
+        let y = y as usize % height;
+        let mut rows = 0;
+        for row in 0..n as usize {
+            let mut py = y + row;
+            if py >= height {
+                if !self.quirks.wrap {
+                    rows += self.quirks.collision_rows as u8;
+                    continue;
+                }
+                py -= height;
+            }
+            let bits = self.load(self.i as usize + row, Access::Sprite);
+            rows += self.vmem.draw_row(x, py, bits, self.quirks.wrap) as u8;
+        }
+        if self.quirks.collision_rows {
+            rows
+        } else {
+            (rows > 0) as u8
+        }
+    }
+
+    /// Read a byte of memory. Every access an instruction makes goes through
+    /// `load` and `store` so coverage and watchpoints see it.
+    fn load(&mut self, addr: usize, access: Access) -> u8 {
+        let addr = addr % self.mem.len();
+        let value = self.peek(addr);
+        self.cover(addr, access);
+        if self.debug.is_watching() {
+            self.with_debugger(|d, e| d.check_access(e, addr as u16, access, value, value));
+        }
+        value
+    }
+
+    fn store(&mut self, addr: usize, value: u8) {
+        let addr = addr % self.mem.len();
+        let old = self.peek(addr);
+        if self.mem.is_protected(addr) {
+            // PC has moved past the instruction storing: FX33, FX55, 2NNN
+            // pushing onto the VIP stack, or 0NNN whose machine code writes.
+            // The original interpreter keeps its PC on the CDP1802 instead,
+            // so its writes are reported once per address.
+            let pc = self.pc.wrapping_sub(2);
+            let key = if self.interpreter { addr as u16 } else { pc };
+            if self.mem.violation(key) {
+                let by = if self.interpreter {
+                    "the interpreter".to_string()
+                } else {
+                    let instr = disasm::disassemble(Emulator::read_word(&self.mem, pc));
+                    format!("{} at 0x{:03X}", instr, pc)
+                };
+                println!(
+                    "Ignored write of 0x{:02X} to read-only 0x{:03X} by {}",
+                    value, addr, by
+                );
+            }
+        } else {
+            self.poke(addr, value);
+            if self.mem.display_offset(addr).is_none() {
+                self.cover(addr, Access::Write);
+            }
+        }
+        if self.debug.is_watching() {
+            let new = self.peek(addr);
+            self.with_debugger(|d, e| d.check_access(e, addr as u16, Access::Write, old, new));
+        }
+    }
+
+    /// Write a byte as a program would, without the protection and hooks of
+    /// `store`.
+    fn poke(&mut self, addr: usize, value: u8) {
+        let addr = addr % self.mem.len();
+        match self.mem.display_offset(addr) {
+            Some(offset) => self.vmem.set_byte(offset, value),
+            None => {
+                self.mem[addr] = value;
+                self.decoded.invalidate(addr);
+            }
+        }
+    }
 
-        self.run_instr(instr)
+    /// Read a byte as a program would, without the hooks of `load`.
+    fn peek(&self, addr: usize) -> u8 {
+        let addr = addr % self.mem.len();
+        match self.mem.display_offset(addr) {
+            Some(offset) => self.vmem.byte(offset),
+            None => self.mem[addr],
+        }
     }
 
+    fn cover(&mut self, addr: usize, access: Access) {
+        if let Some(coverage) = self.coverage.as_mut() {
+            let kind = match access {
+                Access::Execute => coverage::EXECUTED,
+                Access::Sprite => coverage::SPRITE,
+                Access::Read => coverage::READ,
+                Access::Write => coverage::WRITTEN,
+            };
+            coverage.mark(addr, 1, kind);
+        }
+    }
+
+    fn trace(&mut self, pc: u16, instr: u16, executed: bool) {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.log(self, self.cycles, pc, instr, executed);
+            self.tracer = Some(tracer);
+        }
+    }
+
+    #[cfg(test)]
     fn run_instr(&mut self, instr: u16) {
-        // Split the instruction into relevant parts
-        let t = (instr & 0xf000) >> 12;
-        let x = ((instr & 0x0f00) >> 8) as usize;
-        let y = ((instr & 0x00f0) >> 4) as usize;
-        let n = (instr & 0x000f) as u8;
-        let nn = (instr & 0x00ff) as u8;
-        let nnn = instr & 0x0fff;
-        let vx = self.v[x];
-        let vy = self.v[y];
-        match (t, x, y, n) {
+        self.execute(decode::decode(instr));
+    }
+
+    fn execute(&mut self, op: Op) {
+        match op {
             // clear screen
-            (0x0, 0x0, 0xe, 0x0) => self.vmem.fill(0),
+            Op::Cls => self.vmem.clear(),
             // return from subroutine
-            (0x0, 0x0, 0xe, 0xe) => self.pc = self.stack.pop().unwrap(),
+            Op::Ret => self.pc = self.pop(),
             // jump
-            (0x1, _, _, _) => self.pc = nnn,
+            Op::Jump(nnn) => self.pc = nnn,
             // call subroutine
-            (0x2, _, _, _) => {
-                self.stack.push(self.pc);
+            Op::Call(nnn) => {
+                self.push(self.pc);
                 self.pc = nnn;
             }
             // skip if vx eq
-            (0x3, _, _, _) => {
-                if vx == nn {
-                    self.pc += 2
+            Op::SkipEqByte(x, nn) => {
+                if self.v[x] == nn {
+                    self.pc = self.pc.wrapping_add(2)
                 }
             }
             // skip if vx neq
-            (0x4, _, _, _) => {
-                if vx != nn {
-                    self.pc += 2
+            Op::SkipNeByte(x, nn) => {
+                if self.v[x] != nn {
+                    self.pc = self.pc.wrapping_add(2)
                 }
             }
             // skip if vx eq vy
-            (0x5, _, _, _) => {
-                if vx == vy {
-                    self.pc += 2
+            Op::SkipEq(x, y) => {
+                if self.v[x] == self.v[y] {
+                    self.pc = self.pc.wrapping_add(2)
                 }
             }
             // set register vx
-            (0x6, _, _, _) => self.v[x] = nn,
+            Op::SetByte(x, nn) => self.v[x] = nn,
             // add value register vx
-            (0x7, _, _, _) => self.v[x] = vx.wrapping_add(nn),
+            Op::AddByte(x, nn) => self.v[x] = self.v[x].wrapping_add(nn),
             // set vx to vy
-            (0x8, _, _, 0) => self.v[x] = vy,
-            // set vx to vx OR vy
-            (0x8, _, _, 1) => self.v[x] = vx | vy,
-            // set vx to vx AND vy
-            (0x8, _, _, 2) => self.v[x] = vx & vy,
-            // set vx to vx XOR vy
-            (0x8, _, _, 3) => self.v[x] = vx ^ vy,
-            // set vx to vx XOR vy
-            (0x8, _, _, 4) => {
+            Op::Set(x, y) => self.v[x] = self.v[y],
+            // set vx to vx OR vy, AND vy or XOR vy
+            Op::Logic(x, y, n) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                self.v[x] = match n {
+                    1 => vx | vy,
+                    2 => vx & vy,
+                    _ => vx ^ vy,
+                };
+                if self.quirks.logic {
+                    self.v[0xf] = 0;
+                }
+            }
+            // set vx to vx + vy
+            Op::Add(x, y) => {
                 let wrapped: bool;
-                (self.v[x], wrapped) = vx.overflowing_add(vy);
+                (self.v[x], wrapped) = self.v[x].overflowing_add(self.v[y]);
                 self.v[0xf] = if wrapped { 1 } else { 0 }
             }
             // set vx to vx - vy
-            (0x8, _, _, 5) => {
+            Op::Sub(x, y) => {
                 let wrapped: bool;
-                (self.v[x], wrapped) = vx.overflowing_sub(vy);
+                (self.v[x], wrapped) = self.v[x].overflowing_sub(self.v[y]);
                 self.v[0xf] = if wrapped { 0 } else { 1 }
             }
             // set vx to vy - vx
-            (0x8, _, _, 7) => {
+            Op::SubN(x, y) => {
                 let wrapped: bool;
-                (self.v[x], wrapped) = vy.overflowing_sub(vx);
+                (self.v[x], wrapped) = self.v[y].overflowing_sub(self.v[x]);
                 self.v[0xf] = if wrapped { 0 } else { 1 }
             }
-            // set vx to vy and shift right
-            (0x8, _, _, 6) => {
-                self.v[0xf] = vx & 0b00000001;
-                self.v[x] = vy >> 1;
+            // set vx to vy and shift right, or shift vx with the shift quirk;
+            // VF gets the bit shifted out of the old vx either way
+            Op::ShiftRight(x, y) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                if self.quirks.shift {
+                    self.v[x] = vx >> 1;
+                    self.v[0xf] = vx & 0b00000001;
+                } else {
+                    self.v[0xf] = vx & 0b00000001;
+                    self.v[x] = vy >> 1;
+                }
             }
-            // set vx to vy and shift left
-            (0x8, _, _, 0xe) => {
-                self.v[0xf] = (vx & 0b10000000) >> 7;
-                self.v[x] = vy << 1;
+            // set vx to vy and shift left, or shift vx with the shift quirk;
+            // VF gets the bit shifted out of the old vx either way
+            Op::ShiftLeft(x, y) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                if self.quirks.shift {
+                    self.v[x] = vx << 1;
+                    self.v[0xf] = (vx & 0b10000000) >> 7;
+                } else {
+                    self.v[0xf] = (vx & 0b10000000) >> 7;
+                    self.v[x] = vy << 1;
+                }
             }
             // skip if vx neq vy
-            (0x9, _, _, _) => {
-                if vx != vy {
-                    self.pc += 2
+            Op::SkipNe(x, y) => {
+                if self.v[x] != self.v[y] {
+                    self.pc = self.pc.wrapping_add(2)
                 }
             }
             // set index register
-            (0xa, _, _, _) => self.i = nnn,
-            // jump with offset
-            (0xb, _, _, _) => self.pc = self.v[0] as u16 + nnn,
+            Op::SetI(nnn) => self.i = nnn,
+            // jump with offset, BXNN adds vx with the jump quirk
+            Op::JumpOffset(x, nnn) => {
+                let offset = if self.quirks.jump {
+                    self.v[x]
+                } else {
+                    self.v[0]
+                };
+                self.pc = offset as u16 + nnn;
+            }
             // random
-            (0xc, _, _, _) => self.v[x] = rand::random::<u8>() & nn,
+            Op::Random(x, nn) => self.v[x] = self.rng.gen::<u8>() & nn,
             // draw
-            (0xd, _, _, _) => {
-                let dx = vx & 63;
-                let dy = vy & 31;
-                self.v[0xf] = 0;
-                let sprite = &self.mem[self.i as usize..(self.i + n as u16) as usize];
-                for j in 0..n {
-                    let spr_row = sprite[j as usize];
-                    for i in 0..8 {
-                        let v = spr_row >> (7 - i) & 0x1;
-                        if v == 1 {
-                            let idx = ((dx + i) % WIDTH as u8) as usize
-                                + ((dy + j) % HEIGHT as u8) as usize * 64;
-                            let ov = self.vmem[idx];
-                            self.v[0xf] = ov; // If old value was 1 then we mark collision
-                            self.vmem[idx] = v ^ ov;
-                        }
-                    }
-                }
+            Op::Draw(x, y, n) => {
+                self.v[0xf] = self.draw_sprite(self.v[x], self.v[y], n);
+                self.wait_for_vblank = self.quirks.vblank;
             }
             // skip if key down
-            (0xe, _, 0x9, 0xe) => self.pc += if self.keypad[vx as usize] { 2 } else { 0 },
+            Op::SkipKey(x) => {
+                if self.keypad[self.v[x] as usize] {
+                    self.pc = self.pc.wrapping_add(2)
+                }
+            }
             // skip if key up
-            (0xe, _, 0xa, 0x1) => self.pc += if !self.keypad[vx as usize] { 2 } else { 0 },
+            Op::SkipNotKey(x) => {
+                if !self.keypad[self.v[x] as usize] {
+                    self.pc = self.pc.wrapping_add(2)
+                }
+            }
             // get dt val
-            (0xf, _, 0x0, 0x7) => self.v[x] = self.dt,
+            Op::GetDelay(x) => self.v[x] = self.dt,
             // set dt val
-            (0xf, _, 0x1, 0x5) => self.dt = vx,
+            Op::SetDelay(x) => self.dt = self.v[x],
+            // set st val
+            Op::SetSound(x) => self.st = self.v[x],
+            // load audio pattern (XO-CHIP)
+            Op::Audio => {
+                let mut pattern = [0x0; 16];
+                for (i, b) in pattern.iter_mut().enumerate() {
+                    *b = self.load(self.i as usize + i, Access::Read);
+                }
+                self.pattern = Some(pattern);
+            }
+            // set pitch (XO-CHIP)
+            Op::Pitch(x) => self.pitch = self.v[x],
             // get key
-            (0xf, _, 0x0, 0xa) => {
-                if let Some(k) = self.keypad.iter().position(|e| *e) {
-                    self.v[x] = k as u8;
+            Op::WaitKey(x) => {
+                // only keys released from now on count, so a key held down
+                // doesn't satisfy every wait
+                if self.waiting_for_key.is_none() {
+                    self.waiting_for_key = Some(x);
+                    self.released = 0;
+                }
+                if self.released != 0 {
+                    self.v[x] = self.released.trailing_zeros() as u8;
+                    self.waiting_for_key = None;
                 } else {
-                    self.pc -= 2;
+                    self.pc = self.pc.wrapping_sub(2);
                 }
             }
             // binary-coded decimal conversion
-            (0xf, _, 0x3, 0x3) => {
-                self.mem[self.i as usize] = vx / 100;
-                self.mem[self.i as usize + 1] = (vx / 10) % 10;
-                self.mem[self.i as usize + 2] = (vx % 100) % 10;
+            Op::Bcd(x) => {
+                let vx = self.v[x];
+                self.store(self.i as usize, vx / 100);
+                self.store(self.i as usize + 1, (vx / 10) % 10);
+                self.store(self.i as usize + 2, (vx % 100) % 10);
             }
             // font character
-            (0xf, _, 0x2, 0x9) => self.i = ((vx & 0x0f) + 0x50) as u16,
+            Op::Font(x) => self.i = 0x50 + (self.v[x] & 0x0f) as u16 * 5,
             // store mem
-            (0xf, _, 0x5, 0x5) => {
+            Op::Store(x) => {
                 for i in 0..=x {
-                    self.mem[self.i as usize + i as usize] = self.v[i as usize]
+                    self.store(self.i as usize + i, self.v[i])
                 }
+                self.increment_i(x);
             }
             // load mem
-            (0xf, _, 0x6, 0x5) => {
+            Op::LoadRegs(x) => {
                 for i in 0..=x {
-                    self.v[i as usize] = self.mem[self.i as usize + i as usize]
+                    self.v[i] = self.load(self.i as usize + i, Access::Read)
                 }
+                self.increment_i(x);
             }
             // add to i
-            (0xf, _, 0x1, 0xe) => {
-                self.i += self.v[x] as u16;
-                if self.i > 0x0fff {
-                    // amiga specific behaviour
+            Op::AddI(x) => {
+                self.i = self.i.wrapping_add(self.v[x] as u16);
+                if self.i as usize >= self.mem.len() {
+                    // amiga specific behaviour, for I past the end of 4 KiB
                     self.v[0xf] = 1;
                 }
             }
 
+            // call machine code
+            Op::Sys(nnn) if self.vip => {
+                let cycles = vip::call(self, nnn);
+                if let Some(timing) = self.timing.as_mut() {
+                    timing.spend(cycles);
+                }
+            }
+            Op::Sys(nnn) => Emulator::missing(nnn),
+
             // unimplemented instruction
-            (t, x, y, n) => println!(
-                "missing instr {:#02x?} {:#02x?} {:#02x?} {:#02x?}",
-                t, x, y, n
-            ),
+            Op::Unknown(instr) => Emulator::missing(instr),
         }
     }
 
-    fn read_word(mem: [u8; 4096], addr: u16) -> u16 {
-        (mem[addr as usize] as u16) << 8 | (mem[addr as usize + 1] as u16)
+    fn missing(instr: u16) {
+        println!(
+            "missing instr {:#02x?} {:#02x?} {:#02x?} {:#02x?}",
+            instr >> 12,
+            (instr >> 8) & 0xf,
+            (instr >> 4) & 0xf,
+            instr & 0xf
+        )
+    }
+
+    /// Keep the stack and display in memory where the COSMAC VIP interpreter
+    /// has them, for programs that read or overwrite them.
+    fn use_vip_layout(&mut self) {
+        self.vip = true;
+        self.stack_pointer = Some(memory::VIP_STACK_TOP);
+        self.mem.map_display(memory::VIP_DISPLAY, self.vmem.len());
+    }
+
+    /// Push a return address.
+    fn push(&mut self, addr: u16) {
+        match self.stack_pointer {
+            Some(sp) => {
+                let sp = sp.wrapping_sub(2);
+                self.store(sp as usize, (addr >> 8) as u8);
+                self.store(sp as usize + 1, addr as u8);
+                self.stack_pointer = Some(sp);
+            }
+            None => self.stack.push(addr),
+        }
+    }
+
+    /// Pop a return address.
+    fn pop(&mut self) -> u16 {
+        match self.stack_pointer {
+            Some(sp) => {
+                let addr = (self.load(sp as usize, Access::Read) as u16) << 8
+                    | self.load(sp as usize + 1, Access::Read) as u16;
+                self.stack_pointer = Some(sp.wrapping_add(2));
+                addr
+            }
+            None => self.stack.pop().unwrap(),
+        }
+    }
+
+    /// Number of return addresses on the stack.
+    fn stack_depth(&self) -> usize {
+        match self.stack_pointer {
+            Some(sp) => memory::VIP_STACK_TOP.wrapping_sub(sp) as usize / 2,
+            None => self.stack.len(),
+        }
+    }
+
+    /// Move I past the registers stored or loaded by `FX55` and `FX65`.
+    fn increment_i(&mut self, x: usize) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let n = if self.quirks.memory_increment_by_x {
+            x
+        } else {
+            x + 1
+        };
+        self.i = self.i.wrapping_add(n as u16);
+    }
+
+    fn read_word(mem: &[u8], addr: u16) -> u16 {
+        let addr = addr as usize;
+        (mem[addr % mem.len()] as u16) << 8 | (mem[(addr + 1) % mem.len()] as u16)
     }
 }
 
@@ -550,6 +1534,18 @@ mod tests {
         assert_eq!(e.v[0xf], 0x1, "flag should have value of overflowed bit");
     }
 
+    #[test]
+    fn emulator_instr_shift_sets_vf_from_vx_by_default() {
+        let mut e = Emulator::new();
+        e.v[0] = 0b10000001;
+        e.v[1] = 0b00000100;
+        e.run_instr(0x8016);
+        assert_eq!((e.v[0], e.v[0xf]), (0b00000010, 1));
+        e.v[0] = 0b10000000;
+        e.run_instr(0x801e);
+        assert_eq!((e.v[0], e.v[0xf]), (0b00001000, 1));
+    }
+
     #[test]
     fn emulator_instr_subroutine_call() {
         let mut e = Emulator::new();
@@ -597,14 +1593,26 @@ mod tests {
         for _ in 0..20 {
             println!("hi");
             e.run_instr(0xc00f);
-            assert_eq!(
+            assert!(
                 e.v[0] < 0xf0,
-                true,
                 "the random number should be smaller than 0xf0"
             );
         }
     }
 
+    #[test]
+    fn emulator_instr_rand_is_repeatable_with_a_seed() {
+        let mut a = Emulator::new();
+        let mut b = Emulator::new();
+        a.rng = StdRng::seed_from_u64(8);
+        b.rng = StdRng::seed_from_u64(8);
+        for _ in 0..20 {
+            a.run_instr(0xc0ff);
+            b.run_instr(0xc0ff);
+            assert_eq!(a.v[0], b.v[0]);
+        }
+    }
+
     #[test]
     fn emulator_instr_display() {
         let mut e = Emulator::new();
@@ -614,8 +1622,92 @@ mod tests {
         e.v[0] = 0;
         e.v[1] = 3;
         e.run_instr(0xd012);
-        assert_eq!(e.vmem[3 * 64..3 * 64 + 8], [1, 1, 0, 0, 1, 1, 0, 0]);
-        assert_eq!(e.vmem[4 * 64..4 * 64 + 8], [0, 1, 0, 1, 0, 1, 0, 1]);
+        assert_eq!(
+            e.vmem.to_pixels()[3 * 64..3 * 64 + 8],
+            [1, 1, 0, 0, 1, 1, 0, 0]
+        );
+        assert_eq!(
+            e.vmem.to_pixels()[4 * 64..4 * 64 + 8],
+            [0, 1, 0, 1, 0, 1, 0, 1]
+        );
+    }
+
+    #[test]
+    fn emulator_instr_display_sets_vf_on_any_collision() {
+        let mut e = Emulator::new();
+        e.mem[0x300] = 0b11000000;
+        e.mem[0x301] = 0b10000000;
+        e.i = 0x300;
+        e.run_instr(0xd011);
+        assert_eq!(e.v[0xf], 0);
+        // the first pixel collides, a later one in the sprite doesn't
+        e.vmem.set(1, 0, false);
+        e.run_instr(0xd011);
+        assert_eq!(e.v[0xf], 1);
+        assert_eq!(e.vmem.to_pixels()[0..2], [0, 1]);
+        // a collision in the first row is kept through the second
+        e.vmem.set(0, 0, true);
+        e.vmem.set(1, 0, false);
+        e.run_instr(0xd012);
+        assert_eq!(e.v[0xf], 1);
+        assert_eq!(e.vmem.to_pixels()[0..2], [0, 1]);
+        assert_eq!(e.vmem.to_pixels()[64], 1);
+    }
+
+    #[test]
+    fn emulator_instr_display_wraps_or_clips_at_the_edges() {
+        let mut e = Emulator::new();
+        e.mem[0x300..0x302].copy_from_slice(&[0xff, 0xff]);
+        e.i = 0x300;
+        // the position always wraps
+        e.v[0] = 64 + 60;
+        e.v[1] = 32 + 31;
+        e.run_instr(0xd012);
+        assert_eq!(e.vmem.to_pixels()[31 * 64 + 60..32 * 64], [1; 4]);
+        assert_eq!(e.vmem.to_pixels()[31 * 64..31 * 64 + 4], [1; 4]);
+        assert_eq!(e.vmem.to_pixels()[60..64], [1; 4]);
+        assert_eq!(e.vmem.to_pixels()[0..4], [1; 4]);
+
+        let mut e = Emulator::new();
+        e.quirks.wrap = false;
+        e.mem[0x300..0x302].copy_from_slice(&[0xff, 0xff]);
+        e.i = 0x300;
+        e.v[0] = 64 + 60;
+        e.v[1] = 32 + 31;
+        e.run_instr(0xd012);
+        assert_eq!(e.vmem.to_pixels()[31 * 64 + 60..32 * 64], [1; 4]);
+        assert_eq!(e.vmem.to_pixels().iter().filter(|&&p| p == 1).count(), 4);
+        assert_eq!(e.v[0xf], 0);
+    }
+
+    #[test]
+    fn emulator_instr_display_reads_sprites_across_the_end_of_memory() {
+        let mut e = Emulator::new();
+        e.mem[0xfff] = 0x80;
+        e.mem[0] = 0x80;
+        e.i = 0xfff;
+        e.run_instr(0xd012);
+        assert_eq!((e.vmem.to_pixels()[0], e.vmem.to_pixels()[64]), (1, 1));
+    }
+
+    #[test]
+    fn emulator_instr_display_counts_rows_with_the_collision_rows_quirk() {
+        let mut e = Emulator::new();
+        e.quirks.wrap = false;
+        e.quirks.collision_rows = true;
+        e.mem[0x300..0x304].copy_from_slice(&[0x80, 0x40, 0x20, 0x10]);
+        e.i = 0x300;
+        e.v[1] = 29;
+        e.run_instr(0xd014);
+        // one row off the bottom of the screen
+        assert_eq!(e.v[0xf], 1);
+        e.run_instr(0xd014);
+        // three rows collided, one is clipped
+        assert_eq!(e.v[0xf], 4);
+        e.vmem.set(0, 29, true);
+        e.v[1] = 0;
+        e.run_instr(0xd014);
+        assert_eq!(e.v[0xf], 0);
     }
 
     #[test]
@@ -639,12 +1731,97 @@ mod tests {
         assert_eq!(e.pc, 0x202);
     }
 
+    #[test]
+    fn emulator_instr_set_sound_timer() {
+        let mut e = Emulator::new();
+        e.v[2] = 0x20;
+        e.run_instr(0xf218);
+        assert_eq!(e.st, 0x20);
+        assert!(e.sound().active);
+    }
+
+    #[test]
+    fn emulator_instr_load_audio_pattern() {
+        let mut e = Emulator::new();
+        e.mem[0x300..0x310].copy_from_slice(&[0xaa; 16]);
+        e.i = 0x300;
+        assert_eq!(e.sound().pattern, None);
+        e.run_instr(0xf002);
+        assert_eq!(e.sound().pattern, Some([0xaa; 16]));
+
+        // the pattern wraps around the end of memory
+        e.mem[0xff8..].fill(0x55);
+        e.i = 0xff8;
+        e.run_instr(0xf002);
+        let pattern = e.sound().pattern.unwrap();
+        assert_eq!(pattern[..8], [0x55; 8]);
+        assert_eq!(pattern[8..], e.mem[..8]);
+    }
+
+    #[test]
+    fn emulator_instr_set_pitch() {
+        let mut e = Emulator::new();
+        e.v[1] = 112;
+        e.run_instr(0xf13a);
+        assert_eq!(e.sound().pitch, 112);
+    }
+
+    #[test]
+    fn emulator_runs_frames() {
+        let mut e = Emulator::new();
+        // 0x200: v0 += 1, 0x202: jump to 0x200
+        e.load_rom(&[0x70, 0x01, 0x12, 0x00]);
+        e.ipf = 10;
+        e.dt = 2;
+        e.st = 1;
+        e.run_frame();
+        assert_eq!(e.v[0], 5);
+        assert_eq!(e.dt, 1);
+        assert_eq!(e.st, 0);
+        e.run_frame();
+        e.run_frame();
+        assert_eq!(e.v[0], 15);
+        assert_eq!(e.dt, 0);
+    }
+
     #[test]
     fn emulator_instr_get_key() {
         let mut e = Emulator::new();
+        e.pc = 0x202;
         e.set_key_state(3, true);
         e.run_instr(0xf00a);
-        assert_eq!(e.v[0], 0x3);
+        assert_eq!((e.pc, e.waiting_for_key), (0x200, Some(0)));
+        e.pc = 0x202;
+        e.set_key_state(3, false);
+        e.run_instr(0xf00a);
+        assert_eq!((e.v[0], e.pc, e.waiting_for_key), (0x3, 0x202, None));
+
+        // a key held since before the wait counts once it is released
+        e.set_key_state(5, true);
+        e.run_instr(0xf10a);
+        assert_eq!(e.waiting_for_key, Some(1));
+        e.pc = 0x202;
+        e.run_instr(0xf10a);
+        assert_eq!(e.pc, 0x200);
+        e.pc = 0x202;
+        e.set_key_state(5, false);
+        e.run_instr(0xf10a);
+        assert_eq!(e.v[1], 0x5);
+    }
+
+    #[test]
+    fn emulator_beeps_while_a_key_is_held_for_get_key() {
+        let mut e = Emulator::new();
+        e.key_beep = true;
+        e.pc = 0x202;
+        e.run_instr(0xf00a);
+        assert!(!e.sound().active);
+        e.set_key_state(7, true);
+        assert!(e.sound().active);
+        e.set_key_state(7, false);
+        e.pc = 0x202;
+        e.run_instr(0xf00a);
+        assert!(!e.sound().active);
     }
 
     #[test]
@@ -692,6 +1869,14 @@ mod tests {
         e.run_instr(0xf01e);
         assert_eq!(e.i, 0x1000);
         assert_eq!(e.v[0xf], 0x1);
+
+        // with 64 KiB, I is still in memory
+        let mut e = Emulator::with_memory_size(0x10000);
+        e.v[0] = 0x2;
+        e.v[0xf] = 0x7;
+        e.i = 0xffe;
+        e.run_instr(0xf01e);
+        assert_eq!((e.i, e.v[0xf]), (0x1000, 0x7));
     }
 
     #[test]
@@ -699,10 +1884,123 @@ mod tests {
         let mut e = Emulator::new();
         e.v[0] = 0x5;
         e.run_instr(0xf029);
-        assert_eq!(e.i, 0x55);
+        assert_eq!(e.i, 0x69);
+        assert_eq!(e.mem[0x69..0x6e], [0xF0, 0x80, 0xF0, 0x10, 0xF0]);
         e.v[0] = 0x14;
         e.run_instr(0xf029);
-        assert_eq!(e.i, 0x54);
+        assert_eq!(e.i, 0x64);
+    }
+
+    #[test]
+    fn emulator_tracks_coverage() {
+        let mut e = Emulator::new();
+        e.load_rom(&[0xa3, 0x00, 0xf1, 0x55, 0xd0, 0x05]);
+        e.coverage = Some(Coverage::new(4096, 0x200, 6));
+        for _ in 0..3 {
+            e.process();
+        }
+        let c = e.coverage.as_ref().unwrap();
+        assert_eq!(c.flags(0x200), coverage::EXECUTED);
+        assert_eq!(c.flags(0x205), coverage::EXECUTED);
+        assert_eq!(c.flags(0x300), coverage::WRITTEN | coverage::SPRITE);
+        assert_eq!(c.flags(0x301), coverage::WRITTEN | coverage::SPRITE);
+        assert_eq!(c.flags(0x304), coverage::SPRITE);
+        assert_eq!(c.flags(0x305), 0);
+    }
+
+    #[test]
+    fn emulator_stops_on_watched_memory_access() {
+        let mut e = Emulator::new();
+        e.load_rom(&[0xa3, 0x00, 0xd0, 0x05, 0x00, 0xe0]);
+        e.debug
+            .watchpoints
+            .push(debugger::Watchpoint::parse("0x302-0x3ff r").unwrap());
+        e.run_frame();
+        // the frame ends after the DXYN reading the watched sprite row
+        assert_eq!(e.pc, 0x204);
+        assert_eq!(e.cycles, 2);
+        assert_eq!(
+            e.debug.describe_stop(e.pc),
+            "watchpoint w0 (0x302-0x3FF r): DRW V0, V0, 5 at 0x202 read sprite data 0x302 = 0x00"
+        );
+    }
+
+    #[test]
+    fn emulator_applies_quirks() {
+        let mut e = Emulator::new();
+        e.quirks = Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: false,
+            wrap: false,
+            jump: true,
+            logic: true,
+            vblank: false,
+            collision_rows: false,
+        };
+        e.v[0] = 0b00000011;
+        e.v[1] = 0b10000000;
+        e.run_instr(0x8016);
+        assert_eq!((e.v[0], e.v[0xf]), (0b00000001, 1));
+        e.run_instr(0x8011);
+        assert_eq!((e.v[0], e.v[0xf]), (0b10000001, 0));
+
+        e.v[2] = 0x10;
+        e.run_instr(0xb234);
+        assert_eq!(e.pc, 0x244);
+
+        e.i = 0x300;
+        e.run_instr(0xf255);
+        assert_eq!(e.i, 0x303);
+        e.quirks.memory_increment_by_x = true;
+        e.run_instr(0xf265);
+        assert_eq!(e.i, 0x305);
+
+        // sprites are clipped at the right edge instead of wrapping around
+        e.i = 0x50;
+        e.v[0] = 62;
+        e.v[1] = 0;
+        e.run_instr(0xd011);
+        assert_eq!(&e.vmem.to_pixels()[62..64], [1, 1]);
+        assert_eq!(&e.vmem.to_pixels()[0..2], [0, 0]);
+    }
+
+    #[test]
+    fn read_rom_rejects_roms_too_large_for_memory() {
+        let path = std::env::temp_dir().join(format!("chip8-large-{}.ch8", process::id()));
+        std::fs::write(&path, [0u8; 4096 - ROM_START + 1]).unwrap();
+        let result = read_rom(&path, 4096);
+        let larger = read_rom(&path, 0x10000);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().contains("too large"));
+        assert!(larger.is_ok());
+        assert_eq!(
+            read_rom(Path::new("rom/ibm_logo.ch8"), 4096).unwrap().len(),
+            132
+        );
+    }
+
+    #[test]
+    fn start_applies_quirk_options() {
+        let args = ["--clip", "--collision-rows", "--no-cheats", "game.ch8"];
+        let options = Options::parse(args.iter().map(|a| a.to_string())).unwrap();
+        let (e, _) = start(&[0x12, 0x00], &options, None);
+        assert!(!e.quirks.wrap && e.quirks.collision_rows);
+    }
+
+    #[test]
+    fn restart_keeps_breakpoints_and_resets_state() {
+        let options = Options::parse(["--no-cheats".to_string(), "game.ch8".to_string()]).unwrap();
+        let rom = [0x60, 0x05, 0x12, 0x02];
+        let (mut e, _) = start(&rom, &options, None);
+        e.debug
+            .breakpoints
+            .push(debugger::Breakpoint::parse("0x300").unwrap());
+        e.run_frame();
+        assert_eq!(e.v[0], 5);
+        restart(&mut e, &rom, &options, None);
+        assert_eq!((e.v[0], e.pc), (0, 0x200));
+        assert_eq!(e.debug.breakpoints.len(), 1);
     }
 
     #[test]
@@ -711,10 +2009,194 @@ mod tests {
         e.process()
     }
 
+    #[test]
+    fn emulator_protects_and_maps_memory() {
+        let mut e = Emulator::new();
+        // the font can be written unless it is protected
+        e.i = 0x50;
+        e.v[0] = 0xf1;
+        e.run_instr(0xf055);
+        assert_eq!(e.mem[0x50], 0xf1);
+        e.v[0] = 0xf0;
+        e.i = 0x50;
+        e.run_instr(0xf055);
+        e.mem.protect(0x50..0xa0);
+        // FX55 to the font is ignored
+        e.i = 0x50;
+        e.v[0] = 0xaa;
+        e.pc = 0x202;
+        e.run_instr(0xf055);
+        assert_eq!(e.mem[0x50], 0xf0);
+        assert_eq!(e.mem.violations, 1);
+
+        e.mem.map_display(0xf00, e.vmem.len());
+        e.i = 0xf08;
+        e.run_instr(0xf055);
+        assert_eq!(e.vmem.to_pixels()[64..72], [1, 0, 1, 0, 1, 0, 1, 0]);
+        e.vmem.set(0, 0, true);
+        e.i = 0xf00;
+        e.run_instr(0xf165);
+        assert_eq!(e.v[..2], [0x80, 0x00]);
+        assert_eq!(e.mem[0xf08], 0);
+    }
+
+    #[test]
+    fn emulator_wraps_pc_and_i_at_the_end_of_64k() {
+        let mut e = Emulator::with_memory_size(0x10000);
+        // skip over the last instruction, then ADD I, V0 past 0xFFFF
+        e.mem[0xfffc..].copy_from_slice(&[0x30, 0x00, 0xf0, 0x1e]);
+        e.pc = 0xfffc;
+        e.step();
+        assert_eq!(e.pc, 0x0000);
+        e.pc = 0xfffe;
+        e.i = 0xffff;
+        e.v[0] = 2;
+        e.step();
+        assert_eq!((e.pc, e.i), (0x0000, 0x0001));
+    }
+
+    #[test]
+    fn emulator_calls_machine_code_only_in_the_vip_layout() {
+        let mut e = Emulator::new();
+        // SEP R4, straight back to the interpreter
+        e.mem[0x300] = 0xd4;
+        e.v[0] = 0x42;
+        e.pc = 0x202;
+        e.run_instr(0x0300);
+        assert_eq!((e.mem[0xef0], e.pc), (0, 0x202));
+        assert_eq!(e.cpu, Cdp1802::default());
+
+        e.use_vip_layout();
+        e.run_instr(0x0300);
+        assert_eq!((e.mem[0xef0], e.pc), (0x42, 0x202));
+        assert_eq!(e.cpu.p, 4);
+    }
+
+    #[test]
+    fn emulator_keeps_the_stack_in_memory_like_the_vip() {
+        let mut e = Emulator::new();
+        e.use_vip_layout();
+        e.pc = 0x202;
+        e.run_instr(0x2300);
+        e.pc = 0x302;
+        e.run_instr(0x2400);
+        assert_eq!(e.stack_depth(), 2);
+        assert_eq!(e.mem[0xecc..0xed0], [0x03, 0x02, 0x02, 0x02]);
+
+        // a program overwriting the stack changes where RET goes
+        e.mem[0xecd] = 0x10;
+        e.run_instr(0x00ee);
+        assert_eq!(e.pc, 0x310);
+        e.run_instr(0x00ee);
+        assert_eq!((e.pc, e.stack_depth()), (0x202, 0));
+
+        e.vmem.set(0, 0, true);
+        assert_eq!(e.peek(0xf00), 0x80);
+    }
+
+    #[test]
+    fn emulator_with_vip_timing_ends_frames_at_draws() {
+        let mut e = Emulator::new();
+        e.timing = Some(Timing::default());
+        // V0 += 1, draw, jump back
+        e.load_rom(&[0x70, 0x01, 0xd0, 0x01, 0x12, 0x00]);
+        e.run_frame();
+        assert_eq!((e.cycles, e.pc), (2, 0x204));
+        e.run_frame();
+        assert_eq!((e.cycles, e.pc), (5, 0x204));
+    }
+
+    #[test]
+    fn emulator_draws_once_per_frame_with_the_vblank_quirk() {
+        // draw twice, then jump back
+        let rom = [0xd0, 0x01, 0xd0, 0x01, 0x12, 0x00];
+        let mut e = Emulator::new();
+        e.load_rom(&rom);
+        e.run_frame();
+        assert_eq!(e.cycles, e.ipf as u64);
+
+        let mut e = Emulator::new();
+        e.quirks.vblank = true;
+        e.load_rom(&rom);
+        let mut draws = Vec::new();
+        for _ in 0..4 {
+            let before = e.cycles;
+            e.run_frame();
+            draws.push(e.cycles - before);
+        }
+        // each frame ends at the first sprite it draws
+        assert_eq!(draws, [1, 1, 2, 1]);
+        assert_eq!(e.pc, 0x204);
+    }
+
+    #[test]
+    fn emulator_detects_idle_programs() {
+        let mut e = Emulator::new();
+        // wait for a key, then loop forever
+        e.load_rom(&[0xf0, 0x0a, 0x12, 0x02]);
+        assert_eq!(e.idle(), None);
+        e.run_frame();
+        assert_eq!(e.idle(), Some(Idle::WaitingForKey));
+        e.set_key_state(1, true);
+        e.set_key_state(1, false);
+        e.run_frame();
+        assert_eq!(e.idle(), Some(Idle::Halted));
+    }
+
+    #[test]
+    fn headless_run_ends_when_the_program_halts() {
+        let mut e = Emulator::new();
+        e.load_rom(&[0x60, 0x01, 0x70, 0x01, 0x30, 0x20, 0x12, 0x02, 0x12, 0x08]);
+        headless::run(&mut e, 600, None);
+        assert_eq!((e.v[0], e.pc), (0x20, 0x208));
+        // 97 instructions to get there, and the rest of that frame
+        assert!(e.cycles < 97 + e.ipf as u64);
+    }
+
+    #[test]
+    fn headless_run_records_the_frame_the_program_halts_in() {
+        let path = std::env::temp_dir().join("chip8-rust-headless-halt-test.wav");
+        let mut wav = WavRecorder::create(&path).unwrap();
+        let mut e = Emulator::new();
+        // ST = 10, then halt in the first frame
+        e.load_rom(&[0x60, 0x0a, 0xf0, 0x18, 0x12, 0x04]);
+        headless::run(&mut e, 600, Some(&mut wav));
+        wav.finish().unwrap();
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.duration() as usize, audio::SAMPLES_PER_FRAME);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn emulator_runs_self_modifying_code() {
+        let mut e = Emulator::new();
+        // FX55 overwrites the instruction at 0x200 with 7103, V1 += 3
+        e.load_rom(&[0x60, 0x71, 0xa2, 0x00, 0x61, 0x03, 0xf1, 0x55]);
+        for _ in 0..4 {
+            e.process();
+        }
+        e.pc = 0x200;
+        e.process();
+        assert_eq!((e.v[0], e.v[1]), (0x71, 6));
+
+        // memory written directly, e.g. by the debugger
+        e.mem[0x200..0x202].copy_from_slice(&[0x60, 0x09]);
+        e.pc = 0x200;
+        e.process();
+        assert_eq!(e.v[0], 9);
+
+        // and by a cheat
+        e.poke(0x201, 0x0c);
+        e.pc = 0x200;
+        e.process();
+        assert_eq!(e.v[0], 0x0c);
+    }
+
     #[test]
     fn read_word_reads_16_bits() {
         let mut mem: [u8; 4096] = [0x0; 4096];
         mem[0x10..=0x11].copy_from_slice(&[0x5c, 0xa3]);
-        assert_eq!(Emulator::read_word(mem, 0x10), 0x5ca3);
+        assert_eq!(Emulator::read_word(&mem, 0x10), 0x5ca3);
+        assert_eq!(Emulator::read_word(&mem, 0xfff), 0x0000);
     }
 }
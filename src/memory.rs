@@ -0,0 +1,112 @@
+//! The address space seen by CHIP-8 programs.
+//!
+//! Instructions access memory through `Emulator::load` and `Emulator::store`,
+//! which check the protected regions and the mapped display here and call the
+//! coverage and watchpoint hooks. Cheats read and write with `Emulator::peek`
+//! and `Emulator::poke`, which keep the mapped display in step but skip the
+//! rest. The debugger indexes the bytes directly and bypasses all of it.
+
+use std::collections::HashSet;
+use std::ops::{Deref, DerefMut, Range};
+
+/// The stack of the COSMAC VIP interpreter grows down from 0xECF. It has room
+/// for 12 return addresses, deeper calls overwrite the memory below it.
+pub const VIP_STACK_TOP: u16 = 0xed0;
+/// Display RAM of the COSMAC VIP interpreter.
+pub const VIP_DISPLAY: usize = 0xf00;
+
+pub struct Memory {
+    bytes: Vec<u8>,
+    /// Regions that programs can read but not write.
+    protected: Vec<Range<usize>>,
+    /// Address where the display is mapped, one bit per pixel.
+    display: Option<usize>,
+    display_len: usize,
+    /// Number of writes to protected memory that were ignored.
+    pub violations: u64,
+    /// Instructions already reported for writing to protected memory.
+    reported: HashSet<u16>,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        Memory {
+            bytes: vec![0; size],
+            protected: Vec::new(),
+            display: None,
+            display_len: 0,
+            violations: 0,
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Make a region read-only for programs.
+    pub fn protect(&mut self, range: Range<usize>) {
+        self.protected.push(range);
+    }
+
+    pub fn is_protected(&self, addr: usize) -> bool {
+        self.protected.iter().any(|r| r.contains(&addr))
+    }
+
+    /// Map a display of `pixels` pixels at `addr`, 8 pixels per byte with the
+    /// leftmost in the most significant bit.
+    pub fn map_display(&mut self, addr: usize, pixels: usize) {
+        self.display = Some(addr);
+        self.display_len = pixels / 8;
+    }
+
+    /// Offset into the mapped display of an address, if it is mapped.
+    pub fn display_offset(&self, addr: usize) -> Option<usize> {
+        let start = self.display?;
+        (start..start + self.display_len)
+            .contains(&addr)
+            .then(|| addr - start)
+    }
+
+    /// Count a write to protected memory by the instruction at `pc`, returning
+    /// true the first time that instruction does so.
+    pub fn violation(&mut self, pc: u16) -> bool {
+        self.violations += 1;
+        self.reported.insert(pc)
+    }
+}
+
+impl Deref for Memory {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl DerefMut for Memory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_has_protected_and_mapped_regions() {
+        let mut mem = Memory::new(0x10000);
+        assert_eq!(mem.len(), 0x10000);
+        mem.protect(0x50..0xa0);
+        assert!(!mem.is_protected(0x4f));
+        assert!(mem.is_protected(0x50) && mem.is_protected(0x9f));
+        assert!(!mem.is_protected(0xa0));
+
+        assert_eq!(mem.display_offset(0xf00), None);
+        mem.map_display(0xf00, 64 * 32);
+        assert_eq!(mem.display_offset(0xeff), None);
+        assert_eq!(mem.display_offset(0xf01), Some(1));
+        assert_eq!(mem.display_offset(0x1000), None);
+
+        assert!(mem.violation(0x200));
+        assert!(!mem.violation(0x200));
+        assert_eq!(mem.violations, 2);
+    }
+}
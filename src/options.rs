@@ -0,0 +1,448 @@
+use crate::debugger::{Breakpoint, Watchpoint};
+use crate::display::Scaling;
+use crate::palette::Palette;
+use crate::trace::TraceFilter;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: chip8-rust [OPTIONS] <ROM>
+       chip8-rust trace-diff <TRACE> <TRACE> [--context <LINES>]
+
+Options:
+  --ipf <N>                       instructions executed per 60 Hz frame [default: from the
+                                  ROM database, or 12]
+  --headless                      run without a window
+  --frames <N>                    number of frames to run in headless mode [default: 600]
+  --benchmark                     run --frames frames as fast as possible and report the
+                                  instructions per second, implies --headless
+  --wav <FILE>                    record the beeper to a WAV file
+  --seed <N>                      seed the random number generator used by CXNN
+  --trace <FILE>                  log every executed instruction to a file
+  --trace-range <ADDR-ADDR>       only trace instructions in this address range
+  --trace-ops <MNEMONIC,...>      only trace these instructions, e.g. DRW,CALL
+  --trace-after                   log registers after instead of before execution
+  --trace-limit <LINES>           stop tracing after this many lines [default: 1000000]
+  --profile <FILE>                write a profile of hot spots and subroutines on exit
+  --profile-folded <FILE>         write the profiled call stacks for flame graph tools
+  --coverage <FILE>               write how every byte of memory was used on exit
+  --coverage-image <FILE>         write a PNG map of the memory coverage on exit
+  --debug                         read debugger commands from stdin, 'help' lists them
+  --break <ADDR[ if COND]>        stop before executing ADDR, implies --debug
+  --watch <ADDR[-ADDR][:rwx][ if COND]>
+                                  stop when memory is accessed [default: w], implies --debug
+  --no-cheats                     don't load the cheats saved for the ROM
+  --memory-size <BYTES>           size of the address space, 4096 or 65536 [default: 4096]
+  --protect <ADDR-ADDR>           make memory read-only for programs and report writes to
+                                  it, e.g. 0x050-0x09F for the font
+  --vip                           keep the stack at 0xEA0-0xECF and the display at 0xF00 in
+                                  memory like the COSMAC VIP, and run 0NNN machine code
+  --display-wait                  draw at most one sprite per frame, as the original
+                                  interpreter waits for the display interrupt in DXYN
+  --wrap, --clip                  wrap sprites around the edges of the screen or clip them
+                                  [default: from the ROM database, or wrap]
+  --collision-rows                DXYN sets VF to the number of rows that collided, like SCHIP
+  --key-beep                      beep while a key is held for FX0A, like the COSMAC VIP
+  --vip-timing                    run as many instructions per frame as fit in the time the
+                                  COSMAC VIP takes for them, instead of --ipf
+  --vip-interpreter <FILE>        run the original interpreter from an image of it on an
+                                  emulated CDP1802
+  --map-display <ADDR>            map the display into memory, 8 pixels per byte
+  --palette <NAME|RRGGBB,RRGGBB>  display colours (mono, green, amber, lcd) [default: from the
+                                  ROM database, or mono]
+  --no-database                   don't look up title, quirks, speed and colours of the ROM
+  --fast-forward <N>              speed while fast-forwarding [default: 4]
+  --slow-motion <N>               slow motion runs at 1/N of normal speed [default: 4]
+  --scale <N>                     initial window size as a multiple of the display size
+                                  [default: the size of the last run, or 10]
+  --scaling <MODE>                integer, fit (keep the aspect ratio) or stretch [default:
+                                  integer]
+  --fullscreen                    start in fullscreen
+  --show-fps                      show the frames run per second in the window title
+  --capture-scale <N>             pixel scale of screenshots and GIFs [default: 10]
+  --capture-dir <DIR>             where screenshots and GIFs are written [default: .]
+
+Hotkeys:
+  P    pause/resume
+  N    advance a single frame, pausing first
+  Tab  hold to fast-forward
+  M    toggle slow motion
+  F11  toggle fullscreen
+  F9   switch between integer, fit and stretch scaling
+  F12  save a PNG screenshot
+  F10  start/stop recording an animated GIF
+  F5   restart the ROM
+  F6   reload the ROM from disk, or drop a ROM file on the window to load it";
+
+#[derive(Debug)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub ipf: Option<u32>,
+    pub headless: bool,
+    pub frames: u32,
+    pub benchmark: bool,
+    pub wav: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub trace: Option<PathBuf>,
+    pub trace_filter: TraceFilter,
+    pub trace_after: bool,
+    pub trace_limit: u64,
+    pub profile: Option<PathBuf>,
+    pub profile_folded: Option<PathBuf>,
+    pub coverage: Option<PathBuf>,
+    pub coverage_image: Option<PathBuf>,
+    pub debug: bool,
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub no_cheats: bool,
+    pub memory_size: usize,
+    pub protect: Vec<RangeInclusive<u16>>,
+    pub map_display: Option<u16>,
+    pub vip: bool,
+    pub display_wait: bool,
+    pub wrap: Option<bool>,
+    pub collision_rows: bool,
+    pub key_beep: bool,
+    pub vip_timing: bool,
+    pub vip_interpreter: Option<PathBuf>,
+    pub palette: Option<Palette>,
+    pub no_database: bool,
+    pub fast_forward: u32,
+    pub slow_motion: u32,
+    pub scale: Option<u32>,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    pub show_fps: bool,
+    pub capture_scale: u32,
+    pub capture_dir: PathBuf,
+}
+
+impl Options {
+    /// Parse the command line, not including the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut rom = None;
+        let mut ipf = None;
+        let mut headless = false;
+        let mut frames = 600;
+        let mut benchmark = false;
+        let mut wav = None;
+        let mut seed = None;
+        let mut trace = None;
+        let mut trace_filter = TraceFilter::default();
+        let mut trace_after = false;
+        let mut trace_limit = 1_000_000;
+        let mut profile = None;
+        let mut profile_folded = None;
+        let mut coverage = None;
+        let mut coverage_image = None;
+        let mut debug = false;
+        let mut breakpoints = Vec::new();
+        let mut watchpoints = Vec::new();
+        let mut no_cheats = false;
+        let mut memory_size = 4096;
+        let mut protect = Vec::new();
+        let mut map_display = None;
+        let mut vip = false;
+        let mut display_wait = false;
+        let mut wrap = None;
+        let mut collision_rows = false;
+        let mut key_beep = false;
+        let mut vip_timing = false;
+        let mut vip_interpreter = None;
+        let mut palette = None;
+        let mut no_database = false;
+        let mut fast_forward = 4;
+        let mut slow_motion = 4;
+        let mut scale = None;
+        let mut scaling = Scaling::Integer;
+        let mut fullscreen = false;
+        let mut show_fps = false;
+        let mut capture_scale = 10;
+        let mut capture_dir = PathBuf::from(".");
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--ipf" => ipf = Some(parse_number(&value()?, 1, 100_000)?),
+                "--headless" => headless = true,
+                "--frames" => frames = parse_number(&value()?, 1, u32::MAX)?,
+                "--benchmark" => {
+                    benchmark = true;
+                    headless = true;
+                }
+                "--wav" => wav = Some(PathBuf::from(value()?)),
+                "--seed" => {
+                    let v = value()?;
+                    seed = Some(v.parse().map_err(|_| format!("invalid seed '{}'", v))?)
+                }
+                "--trace" => trace = Some(PathBuf::from(value()?)),
+                "--trace-range" => trace_filter.range = Some(parse_range(&value()?)?),
+                "--trace-ops" => {
+                    trace_filter.ops = value()?
+                        .split(',')
+                        .map(|op| op.trim().to_uppercase())
+                        .collect()
+                }
+                "--trace-after" => trace_after = true,
+                "--trace-limit" => trace_limit = parse_number(&value()?, 1, u32::MAX)? as u64,
+                "--profile" => profile = Some(PathBuf::from(value()?)),
+                "--profile-folded" => profile_folded = Some(PathBuf::from(value()?)),
+                "--coverage" => coverage = Some(PathBuf::from(value()?)),
+                "--coverage-image" => coverage_image = Some(PathBuf::from(value()?)),
+                "--debug" => debug = true,
+                "--break" => {
+                    breakpoints.push(Breakpoint::parse(&value()?)?);
+                    debug = true;
+                }
+                "--watch" => {
+                    watchpoints.push(Watchpoint::parse(&value()?)?);
+                    debug = true;
+                }
+                "--no-cheats" => no_cheats = true,
+                "--memory-size" => {
+                    memory_size = match value()?.as_str() {
+                        "4096" => 4096,
+                        "65536" => 65536,
+                        v => return Err(format!("unsupported memory size '{}'", v)),
+                    }
+                }
+                "--protect" => protect.push(parse_range(&value()?)?),
+                "--vip" => vip = true,
+                "--display-wait" => display_wait = true,
+                "--wrap" => wrap = Some(true),
+                "--clip" => wrap = Some(false),
+                "--collision-rows" => collision_rows = true,
+                "--key-beep" => key_beep = true,
+                "--vip-timing" => vip_timing = true,
+                "--vip-interpreter" => vip_interpreter = Some(PathBuf::from(value()?)),
+                "--map-display" => map_display = Some(parse_address(&value()?)?),
+                "--palette" => palette = Some(Palette::parse(&value()?)?),
+                "--no-database" => no_database = true,
+                "--fast-forward" => fast_forward = parse_number(&value()?, 1, 100)?,
+                "--slow-motion" => slow_motion = parse_number(&value()?, 1, 100)?,
+                "--scale" => scale = Some(parse_number(&value()?, 1, 64)?),
+                "--scaling" => scaling = Scaling::parse(&value()?)?,
+                "--fullscreen" => fullscreen = true,
+                "--show-fps" => show_fps = true,
+                "--capture-scale" => capture_scale = parse_number(&value()?, 1, 64)?,
+                "--capture-dir" => capture_dir = PathBuf::from(value()?),
+                a if a.starts_with("--") => return Err(format!("unknown option {}", a)),
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+
+        Ok(Options {
+            rom: rom.ok_or("No path to rom provided.")?,
+            ipf,
+            headless,
+            frames,
+            benchmark,
+            wav,
+            seed,
+            trace,
+            trace_filter,
+            trace_after,
+            trace_limit,
+            profile,
+            profile_folded,
+            coverage,
+            coverage_image,
+            debug,
+            breakpoints,
+            watchpoints,
+            no_cheats,
+            memory_size,
+            protect,
+            map_display,
+            vip,
+            display_wait,
+            wrap,
+            collision_rows,
+            key_beep,
+            vip_timing,
+            vip_interpreter,
+            palette,
+            no_database,
+            fast_forward,
+            slow_motion,
+            scale,
+            scaling,
+            fullscreen,
+            show_fps,
+            capture_scale,
+            capture_dir,
+        })
+    }
+}
+
+fn parse_number(s: &str, min: u32, max: u32) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(n) if (min..=max).contains(&n) => Ok(n),
+        _ => Err(format!(
+            "expected a number between {} and {}, got '{}'",
+            min, max, s
+        )),
+    }
+}
+
+/// Parse an address, either hexadecimal with a `0x` prefix or decimal.
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("invalid address '{}'", s))
+}
+
+/// Parse an inclusive address range such as `0x200-0x2ff`.
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("invalid range '{}', expected START-END", s))?;
+    Ok(parse_address(start)?..=parse_address(end)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn options_require_a_rom() {
+        assert!(parse(&[]).is_err());
+        assert_eq!(parse(&["game.ch8"]).unwrap().rom, PathBuf::from("game.ch8"));
+    }
+
+    #[test]
+    fn options_parse_capture_settings() {
+        let o = parse(&["--capture-scale", "4", "game.ch8", "--palette", "green"]).unwrap();
+        assert_eq!(o.capture_scale, 4);
+        assert_eq!(o.palette, Some(Palette::GREEN));
+        assert!(parse(&["--capture-scale", "0", "game.ch8"]).is_err());
+        assert!(parse(&["--capture-scale"]).is_err());
+        assert_eq!(o.scale, None);
+        assert_eq!(o.scaling, Scaling::Integer);
+        assert!(parse(&["--bogus", "game.ch8"]).is_err());
+    }
+
+    #[test]
+    fn options_parse_window_settings() {
+        let o = parse(&[
+            "--scale",
+            "4",
+            "--scaling",
+            "stretch",
+            "--fullscreen",
+            "--show-fps",
+            "game.ch8",
+        ])
+        .unwrap();
+        assert_eq!(o.scale, Some(4));
+        assert_eq!(o.scaling, Scaling::Stretch);
+        assert!(o.fullscreen && o.show_fps);
+        assert!(parse(&["--scaling", "zoom", "game.ch8"]).is_err());
+    }
+
+    #[test]
+    fn options_parse_headless_settings() {
+        let o = parse(&[
+            "--headless",
+            "--frames",
+            "60",
+            "--wav",
+            "out.wav",
+            "game.ch8",
+        ])
+        .unwrap();
+        assert!(o.headless);
+        assert_eq!(o.frames, 60);
+        assert_eq!(o.wav, Some(PathBuf::from("out.wav")));
+        assert_eq!(o.ipf, None);
+        assert!(!o.benchmark);
+        assert!(parse(&["--benchmark", "game.ch8"]).unwrap().headless);
+    }
+
+    #[test]
+    fn options_parse_memory_settings() {
+        let o = parse(&[
+            "--memory-size",
+            "65536",
+            "--protect",
+            "0x000-0x1ff",
+            "--map-display",
+            "0xf00",
+            "game.ch8",
+        ])
+        .unwrap();
+        assert_eq!(o.memory_size, 0x10000);
+        assert_eq!(o.protect, [0x000..=0x1ff]);
+        assert_eq!(o.map_display, Some(0xf00));
+        assert_eq!(parse(&["game.ch8"]).unwrap().memory_size, 4096);
+        assert!(parse(&["--memory-size", "8192", "game.ch8"]).is_err());
+    }
+
+    #[test]
+    fn options_parse_quirk_settings() {
+        let o = parse(&["--clip", "--collision-rows", "--display-wait", "game.ch8"]).unwrap();
+        assert_eq!(o.wrap, Some(false));
+        assert!(o.collision_rows && o.display_wait);
+        assert_eq!(parse(&["--wrap", "game.ch8"]).unwrap().wrap, Some(true));
+        let o = parse(&["game.ch8"]).unwrap();
+        assert_eq!(o.wrap, None);
+        assert!(!o.collision_rows && !o.display_wait);
+    }
+
+    #[test]
+    fn options_parse_trace_settings() {
+        let o = parse(&[
+            "--trace",
+            "out.log",
+            "--trace-range",
+            "0x200-0x2ff",
+            "--trace-ops",
+            "drw,CALL",
+            "game.ch8",
+        ])
+        .unwrap();
+        assert_eq!(o.trace, Some(PathBuf::from("out.log")));
+        assert_eq!(o.trace_filter.range, Some(0x200..=0x2ff));
+        assert_eq!(o.trace_filter.ops, ["DRW", "CALL"]);
+        assert!(!o.trace_after);
+        assert!(parse(&["--trace-range", "0x200", "game.ch8"]).is_err());
+    }
+
+    #[test]
+    fn options_parse_debugger_settings() {
+        let o = parse(&[
+            "--break",
+            "0x2a0",
+            "--watch",
+            "0x3f0",
+            "--watch",
+            "0x300-0x30f:rw",
+            "game.ch8",
+        ])
+        .unwrap();
+        assert!(o.debug);
+        assert_eq!(o.breakpoints[0].addr, 0x2a0);
+        assert_eq!(o.watchpoints.len(), 2);
+        assert!(o.watchpoints[1].read && o.watchpoints[1].write);
+        assert!(!parse(&["game.ch8"]).unwrap().debug);
+        assert!(parse(&["--watch", "0x300:z", "game.ch8"]).is_err());
+    }
+
+    #[test]
+    fn parse_address_accepts_hex_and_decimal() {
+        assert_eq!(parse_address("0x2Ff"), Ok(0x2ff));
+        assert_eq!(parse_address("512"), Ok(512));
+        assert!(parse_address("0xg").is_err());
+    }
+}
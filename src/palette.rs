@@ -0,0 +1,87 @@
+/// Colours used when turning the 1-bit display into RGBA pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub off: [u8; 4],
+    pub on: [u8; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::MONO
+    }
+}
+
+impl Palette {
+    pub const MONO: Palette = Palette {
+        off: [0x00, 0x00, 0x00, 0xff],
+        on: [0xff, 0xff, 0xff, 0xff],
+    };
+    pub const GREEN: Palette = Palette {
+        off: [0x0f, 0x1f, 0x0f, 0xff],
+        on: [0x33, 0xff, 0x66, 0xff],
+    };
+    pub const AMBER: Palette = Palette {
+        off: [0x1a, 0x10, 0x00, 0xff],
+        on: [0xff, 0xb0, 0x00, 0xff],
+    };
+    pub const LCD: Palette = Palette {
+        off: [0x9b, 0xbc, 0x0f, 0xff],
+        on: [0x0f, 0x38, 0x0f, 0xff],
+    };
+
+    /// Parse either a named palette or a pair of hex colours, `RRGGBB,RRGGBB`,
+    /// where the first colour is used for unlit pixels.
+    pub fn parse(s: &str) -> Result<Palette, String> {
+        match s {
+            "mono" => return Ok(Palette::MONO),
+            "green" => return Ok(Palette::GREEN),
+            "amber" => return Ok(Palette::AMBER),
+            "lcd" => return Ok(Palette::LCD),
+            _ => {}
+        }
+        match s.split_once(',') {
+            Some((off, on)) => Ok(Palette {
+                off: parse_colour(off)?,
+                on: parse_colour(on)?,
+            }),
+            None => Err(format!("unknown palette '{}'", s)),
+        }
+    }
+
+    pub fn colour(&self, lit: bool) -> [u8; 4] {
+        if lit {
+            self.on
+        } else {
+            self.off
+        }
+    }
+}
+
+/// Parse a `RRGGBB` colour, optionally prefixed with `#`.
+pub fn parse_colour(s: &str) -> Result<[u8; 4], String> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("invalid colour '{}', expected RRGGBB", s));
+    }
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid colour '{}'", s))?;
+    Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 0xff])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_parses_names() {
+        assert_eq!(Palette::parse("amber"), Ok(Palette::AMBER));
+        assert!(Palette::parse("purple").is_err());
+    }
+
+    #[test]
+    fn palette_parses_colour_pairs() {
+        let p = Palette::parse("#102030,ffeedd").unwrap();
+        assert_eq!(p.off, [0x10, 0x20, 0x30, 0xff]);
+        assert_eq!(p.on, [0xff, 0xee, 0xdd, 0xff]);
+        assert!(Palette::parse("102030,fffff").is_err());
+    }
+}
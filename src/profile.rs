@@ -0,0 +1,328 @@
+//! Execution profiler.
+//!
+//! Counts executions per address and per subroutine, following `2NNN`/`00EE`
+//! pairs with a shadow call stack, and keeps track of how much time is spent
+//! waiting: in `FX0A` for a key, or in short loops jumping backwards such as
+//! polling the delay timer.
+
+use crate::disasm;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Backward jumps of at most this many bytes are counted as busy loops.
+const BUSY_LOOP_BYTES: u16 = 6;
+
+#[derive(Default)]
+struct AddrStats {
+    count: u64,
+    instr: u16,
+}
+
+/// A call stack in the tree of all stacks seen, identified by its index.
+struct CallNode {
+    parent: usize,
+    /// Entry address of the subroutine called last.
+    addr: u16,
+    /// Instructions executed with this call stack.
+    count: u64,
+}
+
+/// The call stack of the main program, outside any subroutine.
+const ROOT: usize = 0;
+
+pub struct Profiler {
+    addrs: HashMap<u16, AddrStats>,
+    /// Every call stack seen, the source of the folded output.
+    nodes: Vec<CallNode>,
+    /// Index of a call stack by its parent and the subroutine called.
+    children: HashMap<(usize, u16), usize>,
+    /// The call stack currently executing.
+    node: usize,
+    calls: HashMap<u16, u64>,
+    total: u64,
+    key_wait: u64,
+    busy_loops: u64,
+    frame_instrs: u64,
+    frames: Vec<u64>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler {
+            addrs: HashMap::new(),
+            nodes: vec![CallNode {
+                parent: ROOT,
+                addr: 0,
+                count: 0,
+            }],
+            children: HashMap::new(),
+            node: ROOT,
+            calls: HashMap::new(),
+            total: 0,
+            key_wait: 0,
+            busy_loops: 0,
+            frame_instrs: 0,
+            frames: Vec::new(),
+        }
+    }
+}
+
+impl Profiler {
+    /// Record an executed instruction, `next_pc` is the PC after it ran.
+    pub fn record(&mut self, pc: u16, instr: u16, next_pc: u16) {
+        let stats = self.addrs.entry(pc).or_default();
+        stats.count += 1;
+        stats.instr = instr;
+        self.nodes[self.node].count += 1;
+        self.total += 1;
+        self.frame_instrs += 1;
+
+        match instr & 0xf000 {
+            0x2000 => {
+                let target = instr & 0x0fff;
+                let len = self.nodes.len();
+                let parent = self.node;
+                self.node = *self.children.entry((parent, target)).or_insert(len);
+                if self.node == len {
+                    self.nodes.push(CallNode {
+                        parent,
+                        addr: target,
+                        count: 0,
+                    });
+                }
+                *self.calls.entry(target).or_default() += 1;
+            }
+            0x1000 if next_pc <= pc && pc - next_pc <= BUSY_LOOP_BYTES => {
+                self.busy_loops += ((pc - next_pc) / 2 + 1) as u64;
+            }
+            0xf000 if instr & 0x00ff == 0x0a && next_pc == pc => self.key_wait += 1,
+            _ => {}
+        }
+        if instr == 0x00ee {
+            self.node = self.nodes[self.node].parent;
+        }
+    }
+
+    /// Entry addresses of the subroutines in a call stack, outermost first.
+    fn stack(&self, mut node: usize) -> Vec<u16> {
+        let mut stack = Vec::new();
+        while node != ROOT {
+            stack.push(self.nodes[node].addr);
+            node = self.nodes[node].parent;
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// The call stacks that executed instructions, with their counts.
+    fn stacks(&self) -> impl Iterator<Item = (Vec<u16>, u64)> + '_ {
+        (0..self.nodes.len())
+            .filter(|&n| self.nodes[n].count > 0)
+            .map(|n| (self.stack(n), self.nodes[n].count))
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames.push(self.frame_instrs);
+        self.frame_instrs = 0;
+    }
+
+    fn percent(&self, n: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            n as f64 * 100.0 / self.total as f64
+        }
+    }
+
+    /// A human readable report, hottest addresses and subroutines first.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} instructions in {} frames",
+            self.total,
+            self.frames.len()
+        );
+        if !self.frames.is_empty() {
+            let min = self.frames.iter().min().unwrap();
+            let max = self.frames.iter().max().unwrap();
+            let avg = self.frames.iter().sum::<u64>() as f64 / self.frames.len() as f64;
+            let _ = writeln!(
+                out,
+                "instructions per frame: min {} avg {:.1} max {}",
+                min, avg, max
+            );
+        }
+        let _ = writeln!(
+            out,
+            "waiting for a key (FX0A): {} ({:.1}%)",
+            self.key_wait,
+            self.percent(self.key_wait)
+        );
+        let _ = writeln!(
+            out,
+            "busy loops: {} ({:.1}%)",
+            self.busy_loops,
+            self.percent(self.busy_loops)
+        );
+
+        let _ = writeln!(
+            out,
+            "\nhot spots\n{:>12} {:>6}  addr   instruction",
+            "count", "%"
+        );
+        let mut addrs: Vec<(&u16, &AddrStats)> = self.addrs.iter().collect();
+        addrs.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        for (addr, stats) in addrs {
+            let _ = writeln!(
+                out,
+                "{:>12} {:>5.1}%  0x{:03X}  {}",
+                stats.count,
+                self.percent(stats.count),
+                addr,
+                disasm::disassemble(stats.instr)
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "\nsubroutines\n{:>12} {:>6} {:>12} {:>6} {:>8}  addr",
+            "inclusive", "%", "self", "%", "calls"
+        );
+        let mut subs: HashMap<u16, (u64, u64)> = HashMap::new();
+        for (stack, count) in self.stacks() {
+            if let Some(last) = stack.last() {
+                subs.entry(*last).or_default().1 += count;
+            }
+            let mut seen = stack;
+            seen.sort_unstable();
+            seen.dedup();
+            for addr in seen {
+                subs.entry(addr).or_default().0 += count;
+            }
+        }
+        let mut subs: Vec<(u16, (u64, u64))> = subs.into_iter().collect();
+        subs.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(&b.0)));
+        for (addr, (inclusive, own)) in subs {
+            let _ = writeln!(
+                out,
+                "{:>12} {:>5.1}% {:>12} {:>5.1}% {:>8}  0x{:03X}",
+                inclusive,
+                self.percent(inclusive),
+                own,
+                self.percent(own),
+                self.calls.get(&addr).unwrap_or(&0),
+                addr
+            );
+        }
+        out
+    }
+
+    /// Write the call stacks in the folded format used by flame graph tools,
+    /// e.g. `main;0x2A0;0x312 1234`.
+    pub fn write_folded<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self
+            .stacks()
+            .map(|(stack, count)| {
+                let mut name = "main".to_string();
+                for addr in stack {
+                    let _ = write!(name, ";0x{:03X}", addr);
+                }
+                (name, count)
+            })
+            .collect();
+        stacks.sort();
+        for (name, count) in stacks {
+            writeln!(out, "{} {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiler_counts_addresses_and_subroutines() {
+        let mut p = Profiler::default();
+        p.record(0x200, 0x2300, 0x300);
+        p.record(0x300, 0x6001, 0x302);
+        p.record(0x302, 0x00ee, 0x202);
+        p.record(0x202, 0x2300, 0x300);
+        p.record(0x300, 0x6001, 0x302);
+        p.record(0x302, 0x00ee, 0x204);
+        p.end_frame();
+
+        let mut folded = Vec::new();
+        p.write_folded(&mut folded).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "main 2\nmain;0x300 4\n");
+
+        let report = p.report();
+        assert!(
+            report.starts_with("6 instructions in 1 frames\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("           2  33.3%  0x300  LD V0, 0x01"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("           4  66.7%            4  66.7%        2  0x300"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn profiler_keeps_nested_call_stacks_apart() {
+        let mut p = Profiler::default();
+        p.record(0x200, 0x2300, 0x300);
+        p.record(0x300, 0x2400, 0x400);
+        p.record(0x400, 0x00ee, 0x302);
+        p.record(0x302, 0x00ee, 0x202);
+        p.record(0x202, 0x2400, 0x400);
+        p.record(0x400, 0x00ee, 0x204);
+
+        let mut folded = Vec::new();
+        p.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "main 2\nmain;0x300 2\nmain;0x300;0x400 1\nmain;0x400 1\n"
+        );
+        // a return at the top level is ignored
+        p.record(0x204, 0x00ee, 0x206);
+        assert_eq!(p.node, ROOT);
+    }
+
+    #[test]
+    fn profiler_tracks_waiting() {
+        let mut p = Profiler::default();
+        // waiting for a key re-executes FX0A
+        p.record(0x200, 0xf00a, 0x200);
+        p.record(0x200, 0xf00a, 0x202);
+        // a delay timer polling loop
+        p.record(0x202, 0xf107, 0x204);
+        p.record(0x204, 0x3100, 0x206);
+        p.record(0x206, 0x1202, 0x202);
+        p.end_frame();
+        p.record(0x208, 0x1208, 0x208);
+        p.end_frame();
+
+        let report = p.report();
+        assert!(
+            report.contains("instructions per frame: min 1 avg 3.0 max 5"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("waiting for a key (FX0A): 1 (16.7%)"),
+            "{}",
+            report
+        );
+        assert!(report.contains("busy loops: 4 (66.7%)"), "{}", report);
+    }
+}
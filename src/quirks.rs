@@ -0,0 +1,40 @@
+//! Behaviours that differ between CHIP-8 interpreters, named as in the
+//! community chip-8-database.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6` and `8XYE` shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    /// `FX55` and `FX65` increment I by X.
+    pub memory_increment_by_x: bool,
+    /// `FX55` and `FX65` leave I unchanged, otherwise I is incremented by X + 1.
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites wrap around the edges of the screen instead of being clipped.
+    pub wrap: bool,
+    /// `BXNN` jumps to XNN + VX instead of `BNNN` jumping to NNN + V0.
+    pub jump: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF.
+    pub logic: bool,
+    /// `DXYN` waits for the display interrupt, so at most one sprite is
+    /// drawn per frame.
+    pub vblank: bool,
+    /// `DXYN` sets VF to the number of sprite rows that collided or were
+    /// clipped at the bottom of the screen, as SCHIP does, instead of 1.
+    pub collision_rows: bool,
+}
+
+impl Default for Quirks {
+    /// The behaviour of this emulator before quirks could be configured.
+    fn default() -> Self {
+        Quirks {
+            shift: false,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: true,
+            jump: false,
+            logic: false,
+            vblank: false,
+            collision_rows: false,
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! ROM metadata looked up by the SHA-1 of the ROM.
+//!
+//! The database uses the `programs.json` and `platforms.json` files of the
+//! community chip-8-database. A small set of entries is bundled, and files in
+//! `~/.config/chip8-rust/database/` extend it or override bundled entries, for
+//! example a full copy of the community database.
+
+use crate::config;
+use crate::palette::{self, Palette};
+use crate::quirks::Quirks;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+
+const PROGRAMS: &str = include_str!("../database/programs.json");
+const PLATFORMS: &str = include_str!("../database/platforms.json");
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Program {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkSet>,
+    tickrate: Option<u32>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    name: String,
+    default_tickrate: Option<u32>,
+    #[serde(default)]
+    quirks: QuirkSet,
+}
+
+/// Quirks as given in the database, where a ROM only lists those that differ
+/// from its platform.
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuirkSet {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    logic: Option<bool>,
+    vblank: Option<bool>,
+    collision_rows: Option<bool>,
+}
+
+impl QuirkSet {
+    fn apply(&self, q: &mut Quirks) {
+        let set = |to: &mut bool, from: Option<bool>| *to = from.unwrap_or(*to);
+        set(&mut q.shift, self.shift);
+        set(&mut q.memory_increment_by_x, self.memory_increment_by_x);
+        set(
+            &mut q.memory_leave_i_unchanged,
+            self.memory_leave_i_unchanged,
+        );
+        set(&mut q.wrap, self.wrap);
+        set(&mut q.jump, self.jump);
+        set(&mut q.logic, self.logic);
+        set(&mut q.vblank, self.vblank);
+        set(&mut q.collision_rows, self.collision_rows);
+    }
+}
+
+/// What is known about a ROM.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    /// Name of the platform the ROM was written for.
+    pub platform: Option<String>,
+    pub quirks: Option<Quirks>,
+    /// Instructions per frame.
+    pub tickrate: Option<u32>,
+    pub palette: Option<Palette>,
+    /// What the keys do, e.g. `("left", 0x7)`.
+    pub keys: Vec<(String, u8)>,
+}
+
+#[derive(Default)]
+pub struct Database {
+    programs: Vec<Program>,
+    platforms: Vec<Platform>,
+    /// Index of the program containing each ROM hash.
+    hashes: HashMap<String, usize>,
+}
+
+impl Database {
+    pub fn parse(programs: &str, platforms: &str) -> Result<Database, String> {
+        let mut db = Database::default();
+        db.add_programs(programs)?;
+        db.add_platforms(platforms)?;
+        Ok(db)
+    }
+
+    /// The bundled database extended by the user's files.
+    pub fn load() -> Database {
+        let mut db = Database::parse(PROGRAMS, PLATFORMS).expect("bundled ROM database is invalid");
+        let dir = match config::dir() {
+            Some(dir) => dir.join("database"),
+            None => return db,
+        };
+        for file in ["programs.json", "platforms.json"] {
+            let path = dir.join(file);
+            let result = match fs::read_to_string(&path) {
+                Ok(json) if file == "programs.json" => db.add_programs(&json),
+                Ok(json) => db.add_platforms(&json),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                println!("Failed to load {}: {}", path.display(), e);
+            }
+        }
+        db
+    }
+
+    /// Add programs, replacing the entries of ROMs already known.
+    fn add_programs(&mut self, json: &str) -> Result<(), String> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for program in programs {
+            for hash in program.roms.keys() {
+                self.hashes.insert(hash.to_lowercase(), self.programs.len());
+            }
+            self.programs.push(program);
+        }
+        Ok(())
+    }
+
+    /// Add platforms, replacing those with the same id.
+    fn add_platforms(&mut self, json: &str) -> Result<(), String> {
+        let platforms: Vec<Platform> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for platform in platforms {
+            self.platforms.retain(|p| p.id != platform.id);
+            self.platforms.push(platform);
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let hash = config::rom_hash(rom);
+        let program = &self.programs[*self.hashes.get(&hash)?];
+        let entry = program
+            .roms
+            .iter()
+            .find(|(h, _)| h.to_lowercase() == hash)
+            .map(|(_, rom)| rom)?;
+
+        // the first platform listed is the one the ROM was written for
+        let platform = entry
+            .platforms
+            .first()
+            .and_then(|id| self.platforms.iter().find(|p| p.id == *id));
+        let quirks = platform.map(|p| {
+            let mut quirks = Quirks::default();
+            p.quirks.apply(&mut quirks);
+            if let Some(q) = entry.quirky_platforms.get(&p.id) {
+                q.apply(&mut quirks);
+            }
+            quirks
+        });
+        let palette = entry
+            .colors
+            .as_ref()
+            .and_then(|c| match c.pixels.as_slice() {
+                [off, on, ..] => Some(Palette {
+                    off: palette::parse_colour(off).ok()?,
+                    on: palette::parse_colour(on).ok()?,
+                }),
+                _ => None,
+            });
+        Some(RomInfo {
+            title: program.title.clone(),
+            platform: platform.map(|p| p.name.clone()),
+            quirks,
+            tickrate: entry
+                .tickrate
+                .or_else(|| platform.and_then(|p| p.default_tickrate)),
+            palette,
+            keys: entry.keys.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PROGRAMS: &str = r##"[
+        {
+            "title": "Lives",
+            "authors": ["someone"],
+            "roms": {
+                "A9993E364706816ABA3E25717850C26C9CD0D89D": {
+                    "file": "abc.ch8",
+                    "platforms": ["originalChip8", "modernChip8"],
+                    "quirkyPlatforms": { "originalChip8": { "wrap": true } },
+                    "colors": { "pixels": ["#000000", "#ff8000"], "buzzer": "#ffaa00" },
+                    "keys": { "left": 7, "right": 9 }
+                }
+            }
+        }
+    ]"##;
+    const TEST_PLATFORMS: &str = r#"[
+        {
+            "id": "originalChip8",
+            "name": "Cosmac VIP CHIP-8",
+            "defaultTickrate": 15,
+            "quirks": { "shift": false, "memoryLeaveIUnchanged": false, "wrap": false, "logic": true, "vblank": true }
+        }
+    ]"#;
+
+    #[test]
+    fn database_looks_up_roms_by_hash() {
+        let db = Database::parse(TEST_PROGRAMS, TEST_PLATFORMS).unwrap();
+        assert_eq!(db.lookup(b"abd"), None);
+        let info = db.lookup(b"abc").unwrap();
+        assert_eq!(info.title, "Lives");
+        assert_eq!(info.platform.as_deref(), Some("Cosmac VIP CHIP-8"));
+        assert_eq!(info.tickrate, Some(15));
+        assert_eq!(
+            info.quirks,
+            Some(Quirks {
+                memory_leave_i_unchanged: false,
+                wrap: true,
+                logic: true,
+                vblank: true,
+                ..Quirks::default()
+            })
+        );
+        assert_eq!(info.palette.unwrap().on, [0xff, 0x80, 0x00, 0xff]);
+        assert_eq!(
+            info.keys,
+            [("left".to_string(), 7), ("right".to_string(), 9)]
+        );
+    }
+
+    #[test]
+    fn database_is_extendable() {
+        let mut db = Database::parse(TEST_PROGRAMS, TEST_PLATFORMS).unwrap();
+        db.add_programs(
+            r#"[{ "title": "Renamed", "roms": { "a9993e364706816aba3e25717850c26c9cd0d89d": { "tickrate": 30 } } }]"#,
+        )
+        .unwrap();
+        let info = db.lookup(b"abc").unwrap();
+        assert_eq!((info.title.as_str(), info.tickrate), ("Renamed", Some(30)));
+        assert_eq!(info.quirks, None);
+        assert!(db.add_programs("{").is_err());
+    }
+
+    #[test]
+    fn bundled_database_knows_the_included_roms() {
+        let db = Database::parse(PROGRAMS, PLATFORMS).unwrap();
+        let rom = std::fs::read("rom/ibm_logo.ch8").unwrap();
+        assert_eq!(db.lookup(&rom).unwrap().title, "IBM Logo");
+    }
+}
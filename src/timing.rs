@@ -0,0 +1,137 @@
+//! Instruction timing of the CHIP-8 interpreter on the COSMAC VIP.
+//!
+//! The VIP's CDP1802 runs at 1.76 MHz, 8 clock cycles per machine cycle, so a
+//! 60 Hz frame lasts 3668 machine cycles. Display DMA and the interrupt routine
+//! take part of that, the interpreter has the rest. Instructions are charged
+//! what the interpreter spends on them, after Laurence Scotford's analysis of
+//! its code; the figures are close but not exact for every operand.
+
+use crate::decode::Op;
+use crate::Emulator;
+
+/// Machine cycles per 60 Hz frame.
+const FRAME_CYCLES: i64 = 3668;
+/// Cycles per frame spent on display DMA and the display interrupt.
+const DISPLAY_CYCLES: i64 = 1024 + 29;
+/// Cycles per frame left to the interpreter.
+pub const INTERPRETER_CYCLES: u32 = (FRAME_CYCLES - DISPLAY_CYCLES) as u32;
+/// Fetching and decoding an instruction.
+const FETCH_CYCLES: u32 = 40;
+
+/// Cycles left to spend in the current frame.
+#[derive(Default)]
+pub struct Timing {
+    /// Negative when the last instruction ran over into the next frame.
+    left: i64,
+}
+
+impl Timing {
+    /// Start a frame, adding its budget to what is left of the last one.
+    pub fn begin_frame(&mut self) {
+        self.left = self.left.min(0) + INTERPRETER_CYCLES as i64;
+    }
+
+    pub fn has_cycles(&self) -> bool {
+        self.left > 0
+    }
+
+    /// Charge an instruction. `DXYN` waits for the display interrupt at the
+    /// start of the next frame, giving up what is left of this one.
+    pub fn charge(&mut self, op: Op, cycles: u32) {
+        self.left -= cycles as i64;
+        if let Op::Draw(..) = op {
+            self.left = self.left.min(0);
+        }
+    }
+
+    /// Charge cycles spent in machine code called by `0NNN`.
+    pub fn spend(&mut self, cycles: u32) {
+        self.left -= cycles as i64;
+    }
+}
+
+/// Machine cycles taken by `op` when executed in the state of `e`.
+pub fn cost(e: &Emulator, op: Op) -> u32 {
+    let skip = |taken: bool| if taken { 4 } else { 0 };
+    // crossing into another 256 byte page costs extra
+    let page = |from: u16, to: u16| from >> 8 != to >> 8;
+    FETCH_CYCLES
+        + match op {
+            Op::Cls => 3078,
+            Op::Ret => 10,
+            // plus the machine code, charged when it has run
+            Op::Sys(_) => 26,
+            Op::Jump(_) => 12,
+            Op::Call(_) => 26,
+            Op::SkipEqByte(x, nn) => 10 + skip(e.v[x] == nn),
+            Op::SkipNeByte(x, nn) => 10 + skip(e.v[x] != nn),
+            Op::SkipEq(x, y) => 14 + skip(e.v[x] == e.v[y]),
+            Op::SkipNe(x, y) => 14 + skip(e.v[x] != e.v[y]),
+            Op::SetByte(..) => 6,
+            Op::AddByte(..) => 10,
+            Op::Set(..)
+            | Op::Logic(..)
+            | Op::Add(..)
+            | Op::Sub(..)
+            | Op::SubN(..)
+            | Op::ShiftRight(..)
+            | Op::ShiftLeft(..) => 44,
+            Op::SetI(_) => 12,
+            Op::JumpOffset(_, nnn) => 22 + 2 * page(nnn, nnn + e.v[0] as u16) as u32,
+            Op::Random(..) => 36,
+            Op::Draw(x, _, n) => {
+                // rows not aligned to a byte of display RAM are shifted
+                // across two bytes
+                let row = if e.v[x] % 8 == 0 { 34 } else { 54 };
+                26 + n as u32 * row
+            }
+            Op::SkipKey(x) => 14 + skip(e.keypad[e.v[x] as usize & 0xf]),
+            Op::SkipNotKey(x) => 14 + skip(!e.keypad[e.v[x] as usize & 0xf]),
+            Op::GetDelay(_) | Op::SetDelay(_) | Op::SetSound(_) => 10,
+            Op::WaitKey(_) => 18,
+            Op::AddI(x) => 16 + 6 * page(e.i, e.i.wrapping_add(e.v[x] as u16)) as u32,
+            Op::Font(_) => 16,
+            Op::Bcd(x) => {
+                let v = e.v[x] as u32;
+                80 + 16 * (v / 100 + v / 10 % 10 + v % 10)
+            }
+            Op::Store(x) | Op::LoadRegs(x) => 14 + 14 * (x as u32 + 1),
+            // XO-CHIP instructions the VIP doesn't have
+            Op::Audio | Op::Pitch(_) => 10,
+            Op::Unknown(_) => 26,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cost_depends_on_operands() {
+        let mut e = Emulator::new();
+        assert_eq!(cost(&e, Op::SetByte(0, 1)), 46);
+        assert_eq!(cost(&e, Op::SkipEqByte(0, 0)), 54);
+        assert_eq!(cost(&e, Op::SkipEqByte(0, 1)), 50);
+        assert_eq!(cost(&e, Op::Draw(0, 1, 5)), 40 + 26 + 5 * 34);
+        e.v[0] = 3;
+        assert_eq!(cost(&e, Op::Draw(0, 1, 5)), 40 + 26 + 5 * 54);
+        e.v[0] = 129;
+        assert_eq!(cost(&e, Op::Bcd(0)), 40 + 80 + 16 * 12);
+        assert_eq!(cost(&e, Op::JumpOffset(0, 0x280)), 40 + 24);
+    }
+
+    #[test]
+    fn timing_carries_over_and_waits_for_the_display() {
+        let mut t = Timing::default();
+        t.begin_frame();
+        assert_eq!(t.left, 2615);
+        t.charge(Op::Cls, 3118);
+        assert!(!t.has_cycles());
+        t.begin_frame();
+        assert_eq!(t.left, 2615 - 503);
+        t.charge(Op::Draw(0, 0, 1), 100);
+        assert!(!t.has_cycles());
+        t.begin_frame();
+        assert_eq!(t.left, 2615);
+    }
+}
@@ -0,0 +1,154 @@
+//! Instruction level execution trace.
+//!
+//! Every traced instruction produces one line of `key=value` fields followed by
+//! the disassembly as a comment:
+//!
+//! ```text
+//! cycle=42 pc=0214 op=D015 v=0A000000000000000000000000000001 i=0050 dt=00 st=00 sp=1 mem=8C2F1A07 ; DRW V0, V1, 5
+//! ```
+//!
+//! `v` holds V0 to VF as two hex digits each, `sp` is the depth of the call stack
+//! and `mem` is a FNV-1a hash of the whole address space. The registers are
+//! sampled either before or after the instruction executes.
+
+use crate::disasm;
+use crate::Emulator;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Which instructions to trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only trace instructions located in this address range.
+    pub range: Option<RangeInclusive<u16>>,
+    /// Only trace instructions with one of these mnemonics, e.g. `DRW`.
+    pub ops: Vec<String>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, instr: u16) -> bool {
+        if let Some(range) = &self.range {
+            if !range.contains(&pc) {
+                return false;
+            }
+        }
+        self.ops.is_empty() || self.ops.contains(&disasm::mnemonic(instr))
+    }
+}
+
+pub struct Tracer {
+    out: BufWriter<File>,
+    filter: TraceFilter,
+    after: bool,
+    limit: u64,
+    lines: u64,
+}
+
+impl Tracer {
+    /// Trace to a file, stopping after `limit` lines. With `after` set the state
+    /// is sampled after each instruction has executed instead of before.
+    pub fn create(path: &Path, filter: TraceFilter, after: bool, limit: u64) -> io::Result<Self> {
+        Ok(Tracer {
+            out: BufWriter::new(File::create(path)?),
+            filter,
+            after,
+            limit,
+            lines: 0,
+        })
+    }
+
+    /// Log an instruction, called before and after it executes.
+    pub fn log(&mut self, e: &Emulator, cycle: u64, pc: u16, instr: u16, executed: bool) {
+        if executed != self.after || self.lines > self.limit || !self.filter.matches(pc, instr) {
+            return;
+        }
+        let result = if self.lines == self.limit {
+            writeln!(self.out, "# trace truncated after {} lines", self.limit)
+        } else {
+            writeln!(self.out, "{}", format_line(e, cycle, pc, instr))
+        };
+        self.lines += 1;
+        if let Err(err) = result {
+            println!("Writing trace failed: {}", err);
+            self.lines = self.limit + 1;
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+pub fn format_line(e: &Emulator, cycle: u64, pc: u16, instr: u16) -> String {
+    let mut v = String::with_capacity(32);
+    for r in e.v {
+        let _ = write!(v, "{:02X}", r);
+    }
+    format!(
+        "cycle={} pc={:04X} op={:04X} v={} i={:04X} dt={:02X} st={:02X} sp={} mem={:08X} ; {}",
+        cycle,
+        pc,
+        instr,
+        v,
+        e.i,
+        e.dt,
+        e.st,
+        e.stack_depth(),
+        hash_memory(&e.mem),
+        disasm::disassemble(instr)
+    )
+}
+
+/// 32-bit FNV-1a hash.
+pub fn hash_memory(mem: &[u8]) -> u32 {
+    mem.iter()
+        .fold(0x811c9dc5, |h, b| (h ^ *b as u32).wrapping_mul(0x01000193))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_filter_matches_ranges_and_mnemonics() {
+        let all = TraceFilter::default();
+        assert!(all.matches(0x200, 0x00e0));
+
+        let f = TraceFilter {
+            range: Some(0x300..=0x3ff),
+            ops: vec!["DRW".to_string(), "CLS".to_string()],
+        };
+        assert!(f.matches(0x300, 0xd015));
+        assert!(f.matches(0x3ff, 0x00e0));
+        assert!(!f.matches(0x2fe, 0xd015));
+        assert!(!f.matches(0x300, 0x1200));
+    }
+
+    #[test]
+    fn trace_line_format() {
+        let mut e = Emulator::new();
+        e.v[0] = 0x0a;
+        e.v[0xf] = 0x01;
+        e.i = 0x50;
+        e.dt = 0x3c;
+        e.stack.push(0x202);
+        let line = format_line(&e, 42, 0x214, 0xd015);
+        assert!(
+            line.starts_with(
+                "cycle=42 pc=0214 op=D015 v=0A000000000000000000000000000001 i=0050 dt=3C st=00 sp=1 mem="
+            ),
+            "{}",
+            line
+        );
+        assert!(line.ends_with(" ; DRW V0, V1, 5"), "{}", line);
+    }
+
+    #[test]
+    fn hash_memory_is_fnv1a() {
+        assert_eq!(hash_memory(&[]), 0x811c9dc5);
+        assert_eq!(hash_memory(b"a"), 0xe40c292c);
+    }
+}
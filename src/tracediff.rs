@@ -0,0 +1,298 @@
+//! Finds the first instruction where two execution traces diverge.
+//!
+//! Traces are compared line by line. Besides the format written by `--trace`,
+//! any trace made of whitespace separated `key=value` fields works: fields
+//! missing from either trace are ignored, registers may be given as a packed
+//! `v=` field or as `v0=` to `vf=`, and keys are case insensitive.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Fields compared between the traces, in the order they are reported.
+const FIELDS: [&str; 23] = [
+    "pc", "op", "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "va", "vb", "vc", "vd",
+    "ve", "vf", "i", "dt", "st", "sp", "mem",
+];
+
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    text: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl Entry {
+    fn parse(line: usize, text: &str) -> Option<Entry> {
+        let data = text.split(';').next().unwrap_or("").trim();
+        if data.is_empty() || data.starts_with('#') {
+            return None;
+        }
+        let mut fields = BTreeMap::new();
+        for token in data.split_whitespace() {
+            let (key, value) = match token.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let key = key.to_lowercase();
+            if key == "v" {
+                for (n, chunk) in value.as_bytes().chunks(2).enumerate().take(16) {
+                    let reg = String::from_utf8_lossy(chunk).into_owned();
+                    fields.insert(format!("v{:x}", n), reg);
+                }
+            } else {
+                fields.insert(key, value.to_string());
+            }
+        }
+        Some(Entry {
+            line,
+            text: text.to_string(),
+            fields,
+        })
+    }
+
+    fn cycle(&self) -> String {
+        self.fields
+            .get("cycle")
+            .cloned()
+            .unwrap_or_else(|| "?".to_string())
+    }
+}
+
+/// Compare two field values, numerically when both are numbers.
+fn same_value(key: &str, a: &str, b: &str) -> bool {
+    let radix = if key == "sp" { 10 } else { 16 };
+    let number = |s: &str| u64::from_str_radix(s.trim_start_matches("0x"), radix);
+    match (number(a), number(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.eq_ignore_ascii_case(b),
+    }
+}
+
+fn differences(a: &Entry, b: &Entry) -> Vec<String> {
+    FIELDS
+        .iter()
+        .filter_map(|key| match (a.fields.get(*key), b.fields.get(*key)) {
+            (Some(x), Some(y)) if !same_value(key, x, y) => {
+                Some(format!("{}: {} != {}", key.to_uppercase(), x, y))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+struct Trace<R> {
+    lines: io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> Trace<R> {
+    fn next_entry(&mut self) -> io::Result<Option<Entry>> {
+        for text in self.lines.by_ref() {
+            let text = text?;
+            self.line += 1;
+            if let Some(entry) = Entry::parse(self.line, &text) {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Compare two traces and describe the first divergence, or return `None` when
+/// they match.
+pub fn diff<A: BufRead, B: BufRead>(
+    a: A,
+    b: B,
+    names: (&str, &str),
+    context: usize,
+) -> io::Result<Option<String>> {
+    let mut a = Trace {
+        lines: a.lines(),
+        line: 0,
+    };
+    let mut b = Trace {
+        lines: b.lines(),
+        line: 0,
+    };
+    let mut before: VecDeque<Entry> = VecDeque::with_capacity(context + 1);
+    loop {
+        let (x, y) = (a.next_entry()?, b.next_entry()?);
+        let diffs = match (&x, &y) {
+            (Some(x), Some(y)) => differences(x, y),
+            _ => Vec::new(),
+        };
+        let mut report = match (&x, &y) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) if diffs.is_empty() => {
+                before.push_back(x.unwrap());
+                if before.len() > context {
+                    before.pop_front();
+                }
+                continue;
+            }
+            (Some(x), Some(y)) => {
+                let mut report = format!(
+                    "traces diverge at cycle {} (line {} of {}, line {} of {})\n",
+                    x.cycle(),
+                    x.line,
+                    names.0,
+                    y.line,
+                    names.1
+                );
+                for d in diffs {
+                    let _ = writeln!(report, "  {}", d);
+                }
+                report
+            }
+            (Some(x), None) => format!(
+                "{} ends at line {} while {} continues at cycle {}\n",
+                names.1,
+                b.line,
+                names.0,
+                x.cycle()
+            ),
+            (None, Some(y)) => format!(
+                "{} ends at line {} while {} continues at cycle {}\n",
+                names.0,
+                a.line,
+                names.1,
+                y.cycle()
+            ),
+        };
+
+        report += "\n";
+        for entry in &before {
+            let _ = writeln!(report, "  {}", entry.text);
+        }
+        following(&mut report, "<", x, &mut a, context)?;
+        following(&mut report, ">", y, &mut b, context)?;
+        return Ok(Some(report));
+    }
+}
+
+/// Append the diverging entry and the `context` entries after it.
+fn following<R: BufRead>(
+    report: &mut String,
+    marker: &str,
+    mut entry: Option<Entry>,
+    trace: &mut Trace<R>,
+    context: usize,
+) -> io::Result<()> {
+    for _ in 0..=context {
+        match entry {
+            Some(e) => {
+                let _ = writeln!(report, "{} {}", marker, e.text);
+            }
+            None => break,
+        }
+        entry = trace.next_entry()?;
+    }
+    Ok(())
+}
+
+/// Entry point of `chip8-rust trace-diff`, returns whether the traces match.
+pub fn run(a: &Path, b: &Path, context: usize) -> io::Result<bool> {
+    let open = |p: &Path| File::open(p).map(BufReader::new);
+    let names = (a.to_string_lossy(), b.to_string_lossy());
+    match diff(
+        open(a)?,
+        open(b)?,
+        (names.0.as_ref(), names.1.as_ref()),
+        context,
+    )? {
+        Some(report) => {
+            print!("{}", report);
+            Ok(false)
+        }
+        None => {
+            println!("traces are identical");
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "\
+cycle=0 pc=0200 op=6005 v=00000000000000000000000000000000 i=0000 mem=00000001 ; LD V0, 0x05
+cycle=1 pc=0202 op=7001 v=05000000000000000000000000000000 i=0000 mem=00000001 ; ADD V0, 0x01
+cycle=2 pc=0204 op=A300 v=06000000000000000000000000000000 i=0000 mem=00000001 ; LD I, 0x300
+cycle=3 pc=0206 op=F055 v=06000000000000000000000000000000 i=0300 mem=00000001 ; LD [I], V0
+cycle=4 pc=0208 op=1208 v=06000000000000000000000000000000 i=0300 mem=00000002 ; JP 0x208
+";
+
+    fn run_diff(a: &str, b: &str, context: usize) -> Option<String> {
+        diff(a.as_bytes(), b.as_bytes(), ("a", "b"), context).unwrap()
+    }
+
+    #[test]
+    fn identical_traces_match() {
+        assert_eq!(run_diff(A, A, 2), None);
+    }
+
+    #[test]
+    fn reports_the_first_differing_register() {
+        let b = A.replace(
+            "cycle=2 pc=0204 op=A300 v=06",
+            "cycle=2 pc=0204 op=A300 v=07",
+        );
+        let report = run_diff(A, &b, 1).unwrap();
+        assert!(
+            report.starts_with(
+                "traces diverge at cycle 2 (line 3 of a, line 3 of b)\n  V0: 06 != 07\n"
+            ),
+            "{}",
+            report
+        );
+        assert!(report.contains("\n  cycle=1 pc=0202"), "{}", report);
+        assert!(report.contains("\n< cycle=2 pc=0204"), "{}", report);
+        assert!(report.contains("\n< cycle=3 pc=0206"), "{}", report);
+        assert!(
+            report.contains("\n> cycle=2 pc=0204 op=A300 v=07"),
+            "{}",
+            report
+        );
+        assert!(!report.contains("cycle=4"), "{}", report);
+    }
+
+    #[test]
+    fn reports_memory_divergence() {
+        let b = A.replace("i=0300 mem=00000002", "i=0300 mem=00000003");
+        let report = run_diff(A, &b, 0).unwrap();
+        assert!(report.contains("MEM: 00000002 != 00000003"), "{}", report);
+    }
+
+    #[test]
+    fn reports_a_trace_ending_early() {
+        let b: String = A.lines().take(2).map(|l| format!("{}\n", l)).collect();
+        let report = run_diff(A, &b, 0).unwrap();
+        assert!(
+            report.starts_with("b ends at line 2 while a continues at cycle 2"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn accepts_other_trace_formats() {
+        let other = "\
+# comment
+PC=0x200 V0=00 I=0x000
+PC=0x202 V0=05 I=0x000
+PC=0x204 V0=06 I=0x000
+";
+        let b: String = A.lines().take(3).map(|l| format!("{}\n", l)).collect();
+        assert_eq!(run_diff(other, &b, 0), None);
+        let changed = other.replace("V0=06", "V0=6");
+        assert_eq!(run_diff(&changed, &b, 0), None);
+        let wrong = other.replace("PC=0x204", "PC=0x206");
+        assert!(run_diff(&wrong, &b, 0)
+            .unwrap()
+            .contains("PC: 0x206 != 0204"));
+    }
+}
@@ -0,0 +1,213 @@
+//! The COSMAC VIP around the CDP1802: machine code subroutines called by
+//! `0NNN`, and running the original CHIP-8 interpreter from an image of it.
+//!
+//! The interpreter relies on the interrupt routine of the VIP's monitor ROM,
+//! which is not included. It is replaced by native code that shows the display
+//! page and counts down the timers in R8 every frame.
+
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::debugger::Access;
+use crate::memory;
+use crate::timing;
+use crate::Emulator;
+
+/// V0 to VF of the VIP interpreter.
+const VARIABLES: usize = 0xef0;
+/// Machine cycles a subroutine may take before it is given up on.
+const CALL_LIMIT: u32 = 1_000_000;
+/// Largest interpreter image, it has to fit below the programs.
+pub const MAX_INTERPRETER_SIZE: usize = crate::ROM_START;
+
+impl Bus for Emulator {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.load(addr as usize, Access::Read)
+    }
+
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.load(addr as usize, Access::Execute)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.store(addr as usize, value)
+    }
+
+    /// `OUT 2` selects the key tested by `EF3`.
+    fn output(&mut self, port: u8, value: u8) {
+        if port == 2 {
+            self.key_latch = value & 0xf;
+        }
+    }
+
+    fn input(&mut self, _port: u8) -> u8 {
+        0
+    }
+
+    fn flag(&mut self, n: u8) -> bool {
+        n == 3 && self.keypad[self.key_latch as usize]
+    }
+}
+
+/// Run the machine code subroutine at `addr` with the registers set up as the
+/// VIP interpreter has them, until it returns with `SEP R4`. Returns the
+/// machine cycles it took.
+pub fn call(e: &mut Emulator, addr: u16) -> u32 {
+    let mut cpu = Cdp1802::reset();
+    // R2 is the stack pointer shared with the interpreter, machine code pushes
+    // below the return addresses of the CHIP-8 subroutines being run
+    let sp = e.stack_pointer.unwrap_or(memory::VIP_STACK_TOP);
+    cpu.r[2] = sp.wrapping_sub(1);
+    cpu.x = 2;
+    cpu.r[3] = addr;
+    cpu.p = 3;
+    cpu.r[5] = e.pc;
+    cpu.r[0xa] = e.i;
+    cpu.r[0xb] = (memory::VIP_DISPLAY as u16) & 0xff00;
+    for n in 0..16 {
+        e.store(VARIABLES + n, e.v[n]);
+    }
+
+    let mut cycles = 0;
+    while cpu.p != 4 {
+        if cpu.idle || cycles >= CALL_LIMIT {
+            println!("Machine code at 0x{:03X} did not return", addr);
+            break;
+        }
+        cycles += cpu.step(e);
+    }
+
+    for n in 0..16 {
+        e.v[n] = e.load(VARIABLES + n, Access::Read);
+    }
+    e.i = cpu.r[0xa];
+    e.pc = cpu.r[5];
+    e.cpu = cpu;
+    cycles
+}
+
+/// Load the interpreter `image` at 0 and reset the CPU to run it.
+pub fn boot(e: &mut Emulator, image: &[u8]) {
+    e.mem[..image.len()].copy_from_slice(image);
+    let mut cpu = Cdp1802::reset();
+    // the monitor leaves the last page of RAM in R1.1
+    cpu.r[1] = (e.mem.len() as u16 - 1) & 0xff00;
+    e.cpu = cpu;
+    e.interpreter = true;
+}
+
+/// Run a frame of the interpreter on the CPU, then the interrupt routine.
+pub fn run_frame(e: &mut Emulator) {
+    let mut cpu = std::mem::take(&mut e.cpu);
+    let mut cycles = 0;
+    while cycles < timing::INTERPRETER_CYCLES && !cpu.idle {
+        cycles += cpu.step(e);
+    }
+    if cpu.ie {
+        interrupt(e, &mut cpu);
+    }
+
+    // show the state of the CHIP-8 program to the debugger and tools
+    e.pc = cpu.r[5];
+    e.i = cpu.r[0xa];
+    for n in 0..16 {
+        e.v[n] = e.peek(VARIABLES + n);
+    }
+    e.dt = cpu.r[8] as u8;
+    e.st = cpu.q as u8;
+    e.cpu = cpu;
+}
+
+/// What the monitor's interrupt routine does: display the page in RB.1 and
+/// count down the timer in R8.0 and the tone in R8.1, turning off Q with it.
+fn interrupt(e: &mut Emulator, cpu: &mut Cdp1802) {
+    let page = (cpu.r[0xb] & 0xff00) as usize;
+    for offset in 0..e.vmem.len() / 8 {
+        // the page may be the display mapped with --vip, which is already shown
+        let value = e.peek(page + offset);
+        if e.vmem.byte(offset) != value {
+            e.vmem.set_byte(offset, value);
+        }
+    }
+    let [timer, tone] = cpu.r[8].to_le_bytes();
+    let tone = tone.saturating_sub(1);
+    if tone == 0 {
+        cpu.q = false;
+    }
+    cpu.r[8] = u16::from_le_bytes([timer.saturating_sub(1), tone]);
+    cpu.idle = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coverage::{self, Coverage};
+
+    #[test]
+    fn call_runs_machine_code_on_the_chip8_state() {
+        let mut e = Emulator::new();
+        e.v[1] = 0x41;
+        // R7 = 0xEF1, LDN R7, ADI 1, DEC R7, STR R7, SEP R4
+        e.mem[0x300..0x30b].copy_from_slice(&[
+            0xf8, 0x0e, 0xb7, 0xf8, 0xf1, 0xa7, 0x07, 0xfc, 0x01, 0x27, 0x57,
+        ]);
+        e.mem[0x30b] = 0xd4;
+        e.pc = 0x202;
+        let cycles = call(&mut e, 0x300);
+        assert_eq!((e.v[0], e.v[1]), (0x42, 0x41));
+        assert_eq!(e.pc, 0x202);
+        assert_eq!(cycles, 9 * 2);
+    }
+
+    #[test]
+    fn call_pushes_below_the_chip8_stack() {
+        let mut e = Emulator::new();
+        e.use_vip_layout();
+        // CALL 0x300, then 0x300: SYS 0x400, RET
+        e.load_rom(&[0x23, 0x00, 0x12, 0x02]);
+        e.mem[0x300..0x304].copy_from_slice(&[0x04, 0x00, 0x00, 0xee]);
+        // LDI 0xAA, STXD, STXD, IRX, IRX, SEP R4
+        e.mem[0x400..0x407].copy_from_slice(&[0xf8, 0xaa, 0x73, 0x73, 0x60, 0x60, 0xd4]);
+        for _ in 0..3 {
+            e.process();
+        }
+        assert_eq!(e.mem[0xecc..0xed0], [0xaa, 0xaa, 0x02, 0x02]);
+        assert_eq!((e.pc, e.stack_depth()), (0x202, 0));
+    }
+
+    #[test]
+    fn machine_code_is_covered_as_executed() {
+        let mut e = Emulator::new();
+        e.coverage = Some(Coverage::new(e.mem.len(), 0x200, 0));
+        // LDI 0x12, SEP R4
+        e.mem[0x300..0x303].copy_from_slice(&[0xf8, 0x12, 0xd4]);
+        call(&mut e, 0x300);
+        let coverage = e.coverage.as_ref().unwrap();
+        for addr in 0x300..0x303 {
+            assert_eq!(coverage.flags(addr), coverage::EXECUTED, "0x{:03X}", addr);
+        }
+    }
+
+    #[test]
+    fn interpreter_runs_frames_with_the_interrupt_routine() {
+        let mut e = Emulator::new();
+        // RB.1 = 0F, R7 = 0F00, M(R7) = 80, R8 = 0203, SEQ, IDL
+        let image = [
+            0xf8, 0x0f, 0xbb, 0xb7, 0xf8, 0x00, 0xa7, 0xf8, 0x80, 0x57, 0xf8, 0x02, 0xb8, 0xf8,
+            0x03, 0xa8, 0x7b, 0x00,
+        ];
+        boot(&mut e, &image);
+        assert_eq!(e.cpu.r[1], 0x0f00);
+        e.run_frame();
+        assert!(e.vmem.get(0, 0) && !e.vmem.get(1, 0));
+        assert_eq!(e.cpu.r[8], 0x0102);
+        assert!(e.cpu.q && !e.cpu.idle);
+        assert_eq!((e.dt, e.st), (2, 1));
+
+        // with the display mapped the interrupt shows what was written to it
+        let mut e = Emulator::new();
+        e.use_vip_layout();
+        boot(&mut e, &image);
+        e.run_frame();
+        assert!(e.vmem.get(0, 0) && !e.vmem.get(1, 0));
+        assert_eq!(e.mem[0xf00], 0);
+    }
+}